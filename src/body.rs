@@ -0,0 +1,97 @@
+use std::io::{self, BufRead, Seek, SeekFrom};
+
+use crate::header::HttpHeader;
+
+// The source of a request body, which may need to be sent more than once, for instance when following a redirect
+pub trait BodySource {
+    // None when the size isn't known up front, in which case the body is sent with chunked transfer encoding
+    fn size(&self) -> Option<usize>;
+
+    // Fields sent after a chunked body
+    fn trailers(&self) -> Option<&HttpHeader> {
+        None
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize>;
+
+    // Rewinds to the start of the body, returning false if the source cannot be replayed
+    fn rewind(&mut self) -> io::Result<bool>;
+}
+
+// A body which can only be read once
+pub struct StreamedBody<'a, R: BufRead> {
+    size: Option<usize>,
+    reader: &'a mut R,
+    trailers: Option<&'a HttpHeader>,
+}
+
+impl<'a, R: BufRead> StreamedBody<'a, R> {
+    pub fn new(size: usize, reader: &'a mut R) -> Self {
+        Self {
+            size: Some(size),
+            reader,
+            trailers: None,
+        }
+    }
+
+    // A body which is read until EOF
+    pub fn chunked(reader: &'a mut R, trailers: Option<&'a HttpHeader>) -> Self {
+        Self {
+            size: None,
+            reader,
+            trailers,
+        }
+    }
+}
+
+impl<R: BufRead> BodySource for StreamedBody<'_, R> {
+    fn size(&self) -> Option<usize> {
+        self.size
+    }
+
+    fn trailers(&self) -> Option<&HttpHeader> {
+        self.trailers
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+
+    fn rewind(&mut self) -> io::Result<bool> {
+        // there is nothing to replay when there is no body
+        Ok(self.size == Some(0))
+    }
+}
+
+// A body which is replayed by seeking back to wherever the reader was positioned when the request began
+pub struct RewindableBody<'a, R: BufRead + Seek> {
+    size: usize,
+    reader: &'a mut R,
+    start: u64,
+}
+
+impl<'a, R: BufRead + Seek> RewindableBody<'a, R> {
+    pub fn new(size: usize, reader: &'a mut R) -> io::Result<Self> {
+        let start = reader.stream_position()?;
+        Ok(Self {
+            size,
+            reader,
+            start,
+        })
+    }
+}
+
+impl<R: BufRead + Seek> BodySource for RewindableBody<'_, R> {
+    fn size(&self) -> Option<usize> {
+        Some(self.size)
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read(buf)
+    }
+
+    fn rewind(&mut self) -> io::Result<bool> {
+        self.reader.seek(SeekFrom::Start(self.start))?;
+        Ok(true)
+    }
+}
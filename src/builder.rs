@@ -0,0 +1,215 @@
+use std::{net::IpAddr, sync::Arc, time::Duration};
+
+#[cfg(feature = "tls")]
+use crate::tls::{self, TlsConfig};
+use crate::{
+    connector::{self, Connector, TcpConnector},
+    dns_cache::{CachingResolver, DnsCache},
+    error::Error,
+    pool::SessionPool,
+    proxy::{Proxy, ProxyConfig},
+    redirect::RedirectPolicy,
+    resolver::{Resolver, StaticResolver, DEFAULT_RESOLVER},
+    retry::RetryPolicy,
+    timeout::Timeouts,
+    HttpClient,
+};
+
+pub struct HttpClientBuilder {
+    pool: Option<SessionPool>,
+    pool_tag: Option<String>,
+    timeouts: Timeouts,
+    redirect_policy: RedirectPolicy,
+    retry_policy: RetryPolicy,
+    expect_continue: Option<Duration>,
+    proxy_config: ProxyConfig,
+    connector: Option<Arc<dyn Connector>>,
+    resolver: Option<Arc<dyn Resolver>>,
+    resolve_overrides: Vec<(String, u16, IpAddr)>,
+    dns_cache: Option<DnsCache>,
+    #[cfg(feature = "tls")]
+    tls_config: Option<TlsConfig>,
+}
+
+impl HttpClientBuilder {
+    pub fn new() -> Self {
+        Self {
+            pool: None,
+            pool_tag: None,
+            timeouts: Timeouts::new(),
+            redirect_policy: RedirectPolicy::None,
+            retry_policy: RetryPolicy::none(),
+            expect_continue: None,
+            proxy_config: ProxyConfig::new(),
+            connector: None,
+            resolver: None,
+            resolve_overrides: vec![],
+            dns_cache: None,
+            #[cfg(feature = "tls")]
+            tls_config: None,
+        }
+    }
+
+    // Draws sessions from (and releases them to) the supplied pool, which may be shared with other clients.  When
+    // no pool is supplied the client is given a pool of its own.
+    pub fn pool(mut self, pool: SessionPool) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    // Partitions the pool so that this client only reuses sessions opened by clients carrying the same tag, for
+    // instance when clients sharing a pool connect using different credentials.
+    pub fn pool_tag(mut self, tag: &str) -> Self {
+        self.pool_tag = Some(tag.to_owned());
+        self
+    }
+
+    // Sets the default timeouts for every request, any of which a request may override
+    pub fn timeouts(mut self, timeouts: Timeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.connect = Some(timeout);
+        self
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.read = Some(timeout);
+        self
+    }
+
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.write = Some(timeout);
+        self
+    }
+
+    // Sets the overall deadline for a request, covering the request, the response header and the response body
+    pub fn total_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.total = Some(timeout);
+        self
+    }
+
+    // Determines which redirects are followed, by default none are
+    pub fn redirect_policy(mut self, policy: RedirectPolicy) -> Self {
+        self.redirect_policy = policy;
+        self
+    }
+
+    // Determines which failed requests are attempted again, by default none are
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry_policy = policy;
+        self
+    }
+
+    // Sends "expect: 100-continue" with every request body, holding the body back for up to wait for the server to
+    // accept it.  Should the server answer with a final status instead, the body is never sent.  A zero wait sends the
+    // body straight away, as does a read timeout or total timeout with no time left to wait.
+    pub fn expect_continue(mut self, wait: Duration) -> Self {
+        self.expect_continue = Some(wait);
+        self
+    }
+
+    // Sends every request through the proxy
+    pub fn proxy(mut self, proxy: Proxy) -> Self {
+        self.proxy_config = ProxyConfig::all(proxy);
+        self
+    }
+
+    // Decides per request which proxy to go through, if any, e.g. ProxyConfig::from_env().  By default requests are
+    // sent directly, whatever the environment says.
+    pub fn proxy_config(mut self, proxy_config: ProxyConfig) -> Self {
+        self.proxy_config = proxy_config;
+        self
+    }
+
+    // Opens connections with the supplied connector rather than over tcp.  Sessions opened by different connectors
+    // are never mixed, even in a shared pool.
+    pub fn connector(mut self, connector: impl Connector + 'static) -> Self {
+        self.connector = Some(Arc::new(connector));
+        self
+    }
+
+    // Resolves hostnames with the supplied resolver rather than the system's.  The resolver is used by the default
+    // tcp connector and for socks5:// proxies, a custom connector does its own resolution.
+    pub fn resolver(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.resolver = Some(Arc::new(resolver));
+        self
+    }
+
+    // Pins host and port to addr (which may be given more than once) ahead of the resolver, as curl's --resolve
+    // does.  The host header still carries host.
+    pub fn resolve(mut self, host: &str, port: u16, addr: IpAddr) -> Self {
+        self.resolve_overrides.push((host.to_owned(), port, addr));
+        self
+    }
+
+    // Caches resolved addresses (and failures to resolve) in front of the resolver, see HttpClient::dns_cache to
+    // flush it.  As with the resolver, a custom connector does its own resolution, leaving the cache to socks5://
+    // proxies alone.
+    pub fn dns_cache(mut self, cache: DnsCache) -> Self {
+        self.dns_cache = Some(cache);
+        self
+    }
+
+    // Secures https requests using the supplied tls configuration rather than the bundled root certificates
+    #[cfg(feature = "tls")]
+    pub fn tls_config(mut self, tls_config: TlsConfig) -> Self {
+        self.tls_config = Some(tls_config);
+        self
+    }
+
+    pub fn build(self) -> Result<HttpClient, Error> {
+        let mut resolver = self.resolver.clone();
+        if let Some(cache) = &self.dns_cache {
+            resolver = Some(Arc::new(CachingResolver {
+                cache: cache.clone(),
+                inner: resolver.unwrap_or_else(|| DEFAULT_RESOLVER.clone()),
+            }));
+        }
+        if !self.resolve_overrides.is_empty() {
+            let fallback = resolver.unwrap_or_else(|| DEFAULT_RESOLVER.clone());
+            let mut static_resolver = StaticResolver::with_fallback(fallback);
+            for (host, port, addr) in &self.resolve_overrides {
+                static_resolver.add(host, *port, *addr);
+            }
+            resolver = Some(Arc::new(static_resolver));
+        }
+
+        // the default connector is only shared while resolution is left to the system
+        let connector = match (&self.connector, &resolver) {
+            (Some(connector), _) => connector.clone(),
+            (None, Some(resolver)) => Arc::new(TcpConnector::with_resolver(resolver.clone())),
+            (None, None) => connector::DEFAULT_CONNECTOR.clone(),
+        };
+        let resolver = resolver.unwrap_or_else(|| DEFAULT_RESOLVER.clone());
+
+        #[cfg(feature = "tls")]
+        let tls_config = match &self.tls_config {
+            Some(tls_config) => tls_config.build()?,
+            None => tls::DEFAULT_CLIENT_CONFIG.clone(),
+        };
+
+        return Ok(HttpClient {
+            pool: self.pool.unwrap_or_default(),
+            pool_tag: self.pool_tag,
+            timeouts: self.timeouts,
+            redirect_policy: self.redirect_policy,
+            retry_policy: self.retry_policy,
+            expect_continue: self.expect_continue,
+            proxy_config: self.proxy_config,
+            connector,
+            resolver,
+            dns_cache: self.dns_cache,
+            #[cfg(feature = "tls")]
+            tls_config,
+        });
+    }
+}
+
+impl Default for HttpClientBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,339 @@
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::{
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    sync::{mpsc, Arc, LazyLock},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::resolver::{split_authority, Resolver, DEFAULT_RESOLVER};
+
+// A connected stream as handed out by a connector, over which a session (and any tls) runs.  Streams which have no
+// notion of a timeout may leave the timeout setters as they are, in which case read and write timeouts go unenforced.
+pub trait Connection: Read + Write + Send {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn set_write_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+
+    // The address of the peer, where the stream has one
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+}
+
+impl Connection for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_write_timeout(self, timeout)
+    }
+
+    fn peer_addr(&self) -> Option<SocketAddr> {
+        TcpStream::peer_addr(self).ok()
+    }
+}
+
+#[cfg(unix)]
+impl Connection for UnixStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_read_timeout(self, timeout)
+    }
+
+    fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        UnixStream::set_write_timeout(self, timeout)
+    }
+}
+
+// Opens the streams which sessions run over.  authority is the host:port to connect to, which is the proxy's when
+// going through one, and the connection attempt should give up once timeout (if any) has elapsed.  A TimedOut error
+// is reported as ErrorKind::Timeout, and any other as ErrorKind::Connect.
+pub trait Connector: Send + Sync {
+    fn connect(
+        &self,
+        authority: &str,
+        timeout: Option<Duration>,
+    ) -> io::Result<Box<dyn Connection>>;
+}
+
+// Connects over tcp, the default.  When a host resolves to several addresses they are raced as described by happy
+// eyeballs (RFC 8305): ipv6 and ipv4 addresses are interleaved, a further attempt is started each time the previous
+// one fails or attempt_delay passes without it connecting, and the first attempt to connect wins.  Attempts which lose
+// carry on in the background until they connect or fail, so without a connect timeout each is bounded by
+// attempt_timeout instead.
+#[derive(Clone)]
+pub struct TcpConnector {
+    resolver: Arc<dyn Resolver>,
+    attempt_delay: Duration,
+    attempt_timeout: Duration,
+}
+
+impl Default for TcpConnector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TcpConnector {
+    // Resolves hostnames with the system resolver
+    pub fn new() -> Self {
+        Self::with_resolver(DEFAULT_RESOLVER.clone())
+    }
+
+    pub fn with_resolver(resolver: Arc<dyn Resolver>) -> Self {
+        Self {
+            resolver,
+            attempt_delay: DEFAULT_ATTEMPT_DELAY,
+            attempt_timeout: DEFAULT_ATTEMPT_TIMEOUT,
+        }
+    }
+
+    // Sets how long an attempt is given to connect before the next address is tried alongside it, 250ms by default
+    pub fn with_attempt_delay(mut self, attempt_delay: Duration) -> Self {
+        self.attempt_delay = attempt_delay;
+        self
+    }
+
+    // Sets how long each of several raced attempts is given to connect when there is no connect timeout, 30s by
+    // default
+    pub fn with_attempt_timeout(mut self, attempt_timeout: Duration) -> Self {
+        self.attempt_timeout = attempt_timeout;
+        self
+    }
+}
+
+// The connection attempt delay recommended by RFC 8305
+const DEFAULT_ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+const DEFAULT_ATTEMPT_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl Connector for TcpConnector {
+    fn connect(
+        &self,
+        authority: &str,
+        timeout: Option<Duration>,
+    ) -> io::Result<Box<dyn Connection>> {
+        let Some((host, port)) = split_authority(authority) else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid authority: \"{}\"", authority),
+            ));
+        };
+
+        let addrs = interleave(self.resolver.resolve(&host, port)?);
+        let stream = match addrs.as_slice() {
+            [] => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "host resolved to no addresses",
+                ))
+            }
+            [addr] => connect_addr(addr, timeout)?,
+            _ => race(
+                addrs,
+                self.attempt_delay,
+                timeout.unwrap_or(self.attempt_timeout),
+            )?,
+        };
+        return Ok(Box::new(stream));
+    }
+}
+
+fn connect_addr(addr: &SocketAddr, timeout: Option<Duration>) -> io::Result<TcpStream> {
+    return match timeout {
+        Some(timeout) => TcpStream::connect_timeout(addr, timeout),
+        None => TcpStream::connect(addr),
+    };
+}
+
+// Orders addresses alternately by family, beginning with the family of the most preferred address and otherwise
+// keeping the resolver's order
+fn interleave(addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+    let Some(first) = addrs.first() else {
+        return addrs;
+    };
+
+    let first_v6 = first.is_ipv6();
+    let (mut preferred, mut other): (Vec<SocketAddr>, Vec<SocketAddr>) =
+        addrs.iter().partition(|addr| addr.is_ipv6() == first_v6);
+    preferred.reverse();
+    other.reverse();
+
+    let mut out = Vec::with_capacity(addrs.len());
+    while let Some(addr) = preferred.pop() {
+        out.push(addr);
+        if let Some(addr) = other.pop() {
+            out.push(addr);
+        }
+    }
+    out.extend(other.into_iter().rev());
+    return out;
+}
+
+// Starts an attempt on each address in turn, on a thread of its own, returning the first stream to connect.  Attempts
+// which are still outstanding once there is a winner (or once the timeout has elapsed) are abandoned, and any stream
+// they go on to connect is closed as soon as the attempt finds nobody is listening for it.  As no attempt outlives the
+// timeout, neither do their threads.
+fn race(
+    addrs: Vec<SocketAddr>,
+    attempt_delay: Duration,
+    timeout: Duration,
+) -> io::Result<TcpStream> {
+    let deadline = Instant::now() + timeout;
+    let (tx, rx) = mpsc::channel::<io::Result<TcpStream>>();
+    let mut pending = addrs.into_iter();
+    let mut outstanding = 0;
+    let mut last_err = None;
+
+    loop {
+        // start the next attempt, unless there are no more addresses to try
+        if let Some(addr) = pending.next() {
+            let tx = tx.clone();
+            let attempt_timeout = deadline.saturating_duration_since(Instant::now());
+            thread::spawn(move || {
+                let result = match attempt_timeout.is_zero() {
+                    true => Err(io::ErrorKind::TimedOut.into()),
+                    false => TcpStream::connect_timeout(&addr, attempt_timeout),
+                };
+                // should the race already be over, the stream is dropped and so closed
+                let _ = tx.send(result);
+            });
+            outstanding += 1;
+        }
+
+        // wait for an attempt to finish, or for the delay before the next attempt is started
+        let more = pending.len() > 0;
+        while outstanding > 0 {
+            let mut wait = deadline.saturating_duration_since(Instant::now());
+            if more {
+                wait = wait.min(attempt_delay);
+            }
+
+            let result = match rx.recv_timeout(wait) {
+                Ok(result) => result,
+                Err(_) if more => break,
+                Err(_) => return Err(io::ErrorKind::TimedOut.into()),
+            };
+
+            outstanding -= 1;
+            match result {
+                Ok(stream) => return Ok(stream),
+                // a failure starts the next attempt without waiting out the delay
+                Err(e) => {
+                    last_err = Some(e);
+                    if more {
+                        break;
+                    }
+                }
+            }
+        }
+
+        if outstanding == 0 && pending.len() == 0 {
+            return Err(last_err.unwrap_or_else(|| io::ErrorKind::NotFound.into()));
+        }
+        if Instant::now() >= deadline {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+    }
+}
+
+// Shared by every client which isn't given a connector of its own, so that their sessions can share a pool
+pub(crate) static DEFAULT_CONNECTOR: LazyLock<Arc<dyn Connector>> =
+    LazyLock::new(|| Arc::new(TcpConnector::new()));
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+
+    use super::*;
+    use crate::resolver::StaticResolver;
+
+    #[test]
+    fn test_interleave() {
+        let addrs: Vec<SocketAddr> = [
+            "[::1]:80",
+            "[::2]:80",
+            "[::3]:80",
+            "1.0.0.1:80",
+            "1.0.0.2:80",
+        ]
+        .iter()
+        .map(|a| a.parse().unwrap())
+        .collect();
+        let ordered: Vec<String> = interleave(addrs).iter().map(|a| a.to_string()).collect();
+        assert!(
+            ordered
+                == [
+                    "[::1]:80",
+                    "1.0.0.1:80",
+                    "[::2]:80",
+                    "1.0.0.2:80",
+                    "[::3]:80"
+                ]
+        );
+
+        let addrs: Vec<SocketAddr> = ["1.0.0.1:80", "1.0.0.2:80", "1.0.0.3:80", "[::1]:80"]
+            .iter()
+            .map(|a| a.parse().unwrap())
+            .collect();
+        let ordered: Vec<String> = interleave(addrs).iter().map(|a| a.to_string()).collect();
+        assert!(ordered == ["1.0.0.1:80", "[::1]:80", "1.0.0.2:80", "1.0.0.3:80"]);
+    }
+
+    #[test]
+    fn test_happy_eyeballs() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        // the first address is never answered (or is unreachable), so the second wins
+        let mut resolver = StaticResolver::new();
+        resolver.add("race.invalid", port, "192.0.2.1".parse().unwrap());
+        resolver.add("race.invalid", port, "127.0.0.1".parse().unwrap());
+        let connector = TcpConnector::with_resolver(Arc::new(resolver))
+            .with_attempt_delay(Duration::from_millis(50));
+
+        let started = Instant::now();
+        let stream = connector
+            .connect(
+                &format!("race.invalid:{}", port),
+                Some(Duration::from_secs(5)),
+            )
+            .unwrap();
+        assert!(started.elapsed() < Duration::from_secs(2));
+        assert!(stream.peer_addr() == Some(([127, 0, 0, 1], port).into()));
+
+        // every address failing reports the last failure
+        drop(listener);
+        let mut resolver = StaticResolver::new();
+        resolver.add("refused.invalid", port, "127.0.0.1".parse().unwrap());
+        resolver.add("refused.invalid", port, "127.0.0.2".parse().unwrap());
+        let connector = TcpConnector::with_resolver(Arc::new(resolver));
+        let err = connector
+            .connect(
+                &format!("refused.invalid:{}", port),
+                Some(Duration::from_secs(5)),
+            )
+            .err()
+            .unwrap();
+        assert!(err.kind() == io::ErrorKind::ConnectionRefused);
+
+        // without a connect timeout, attempts which are never answered give up after attempt_timeout
+        let mut resolver = StaticResolver::new();
+        resolver.add("blackhole.invalid", port, "192.0.2.1".parse().unwrap());
+        resolver.add("blackhole.invalid", port, "192.0.2.2".parse().unwrap());
+        let connector = TcpConnector::with_resolver(Arc::new(resolver))
+            .with_attempt_delay(Duration::from_millis(50))
+            .with_attempt_timeout(Duration::from_millis(200));
+        let started = Instant::now();
+        assert!(connector
+            .connect(&format!("blackhole.invalid:{}", port), None)
+            .is_err());
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+}
@@ -0,0 +1,363 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::resolver::Resolver;
+
+// The outcome of a lookup, failures being kept as their kind and message so that they can be handed out repeatedly
+type Lookup = Result<Vec<SocketAddr>, (io::ErrorKind, String)>;
+
+struct CacheEntry {
+    lookup: Lookup,
+    expires: Instant,
+    // set while a background refresh of the entry is under way, so that only one is started
+    refreshing: bool,
+}
+
+struct CacheState {
+    entries: HashMap<(String, u16), CacheEntry>,
+}
+
+// A handle to an in-process cache of resolved addresses, which sits in front of a client's resolver.  Cloning the
+// handle shares the cache, e.g. to flush it from elsewhere, but it should only be shared by clients which resolve
+// hostnames the same way.
+#[derive(Clone)]
+pub struct DnsCache {
+    state: Arc<Mutex<CacheState>>,
+    // how long successful and failed lookups are reused for
+    pub positive_ttl: Duration,
+    pub negative_ttl: Duration,
+    pub max_entries: usize,
+    // when set, an entry used within this long of expiring is resolved again in the background, while lookups carry on
+    // being answered from the cache
+    pub refresh_ahead: Option<Duration>,
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl DnsCache {
+    // Caches successful lookups for 60s and failed ones for 5s, up to 1024 entries, without background refreshes
+    pub fn new() -> Self {
+        Self {
+            state: Arc::new(Mutex::new(CacheState {
+                entries: HashMap::new(),
+            })),
+            positive_ttl: Duration::from_secs(60),
+            negative_ttl: Duration::from_secs(5),
+            max_entries: 1024,
+            refresh_ahead: None,
+        }
+    }
+
+    pub fn positive_ttl(mut self, ttl: Duration) -> Self {
+        self.positive_ttl = ttl;
+        self
+    }
+
+    // Caches failed lookups for ttl, a zero ttl leaves failures uncached
+    pub fn negative_ttl(mut self, ttl: Duration) -> Self {
+        self.negative_ttl = ttl;
+        self
+    }
+
+    // Once full, the entry nearest to expiring makes way for a new one
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.max_entries = max_entries;
+        self
+    }
+
+    // Resolves entries again in the background when they are used within ahead of expiring, so that busy hosts never
+    // wait on resolution
+    pub fn background_refresh(mut self, ahead: Duration) -> Self {
+        self.refresh_ahead = Some(ahead);
+        self
+    }
+
+    // Forgets every cached lookup
+    pub fn flush(&self) {
+        self.state.lock().unwrap().entries.clear();
+    }
+
+    // Forgets the cached lookups for host, on any port
+    pub fn flush_host(&self, host: &str) {
+        let host = host.to_lowercase();
+        let mut state = self.state.lock().unwrap();
+        state
+            .entries
+            .retain(|(entry_host, _), _| *entry_host != host);
+    }
+
+    // The number of lookups cached, including any which have expired but not yet been evicted
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn end_refresh(&self, key: &(String, u16)) {
+        if let Some(entry) = self.state.lock().unwrap().entries.get_mut(key) {
+            entry.refreshing = false;
+        }
+    }
+
+    fn insert(&self, key: (String, u16), lookup: Lookup) {
+        let ttl = match lookup {
+            Ok(_) => self.positive_ttl,
+            Err(_) => self.negative_ttl,
+        };
+        if ttl.is_zero() || self.max_entries == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if !state.entries.contains_key(&key) && state.entries.len() >= self.max_entries {
+            state.entries.retain(|_, entry| entry.expires > now);
+            if state.entries.len() >= self.max_entries {
+                let soonest = state
+                    .entries
+                    .iter()
+                    .min_by_key(|(_, entry)| entry.expires)
+                    .map(|(key, _)| key.clone());
+                if let Some(soonest) = soonest {
+                    state.entries.remove(&soonest);
+                }
+            }
+        }
+
+        state.entries.insert(
+            key,
+            CacheEntry {
+                lookup,
+                expires: now + ttl,
+                refreshing: false,
+            },
+        );
+    }
+}
+
+// Answers lookups from the cache where it can, and from the inner resolver otherwise
+pub(crate) struct CachingResolver {
+    pub cache: DnsCache,
+    pub inner: Arc<dyn Resolver>,
+}
+
+impl Resolver for CachingResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        let key = (host.to_lowercase(), port);
+        let now = Instant::now();
+
+        let cached = {
+            let mut state = self.cache.state.lock().unwrap();
+            match state.entries.get_mut(&key) {
+                Some(entry) if entry.expires > now => {
+                    let refresh = self.cache.refresh_ahead.is_some_and(|ahead| {
+                        !entry.refreshing && entry.expires.duration_since(now) <= ahead
+                    });
+                    if refresh {
+                        entry.refreshing = true;
+                    }
+                    Some((entry.lookup.clone(), refresh))
+                }
+                _ => None,
+            }
+        };
+
+        if let Some((lookup, refresh)) = cached {
+            if refresh {
+                let cache = self.cache.clone();
+                let inner = self.inner.clone();
+                let key = key.clone();
+                thread::spawn(move || {
+                    // a failed refresh leaves the cached addresses to serve out their ttl, and the next use to try
+                    // again
+                    match resolve_uncached(inner.as_ref(), &key.0, key.1) {
+                        Err(_) => cache.end_refresh(&key),
+                        lookup => cache.insert(key, lookup),
+                    }
+                });
+            }
+            return lookup.map_err(|(kind, message)| io::Error::new(kind, message));
+        }
+
+        let lookup = resolve_uncached(self.inner.as_ref(), host, port);
+        self.cache.insert(key, lookup.clone());
+        return lookup.map_err(|(kind, message)| io::Error::new(kind, message));
+    }
+}
+
+fn resolve_uncached(resolver: &dyn Resolver, host: &str, port: u16) -> Lookup {
+    return match resolver.resolve(host, port) {
+        Ok(addrs) if addrs.is_empty() => Err((
+            io::ErrorKind::NotFound,
+            format!("{} resolved to no addresses", host),
+        )),
+        Ok(addrs) => Ok(addrs),
+        Err(e) => Err((e.kind(), e.to_string())),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use super::*;
+
+    // Resolves hosts beginning "ok" to the loopback address and fails everything else (or everything, once told to),
+    // counting lookups
+    #[derive(Default)]
+    struct CountingResolver {
+        lookups: AtomicUsize,
+        failing: AtomicBool,
+    }
+
+    impl Resolver for CountingResolver {
+        fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            if host.to_lowercase().starts_with("ok") && !self.failing.load(Ordering::SeqCst) {
+                return Ok(vec![([127, 0, 0, 1], port).into()]);
+            }
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such host"));
+        }
+    }
+
+    fn caching(cache: &DnsCache) -> (CachingResolver, Arc<CountingResolver>) {
+        let inner = Arc::new(CountingResolver::default());
+        let resolver = CachingResolver {
+            cache: cache.clone(),
+            inner: inner.clone(),
+        };
+        return (resolver, inner);
+    }
+
+    // The expiry of the cached lookup for host on port 80, and whether it is being refreshed
+    fn entry(cache: &DnsCache, host: &str) -> (Instant, bool) {
+        let state = cache.state.lock().unwrap();
+        let entry = &state.entries[&(host.to_string(), 80)];
+        return (entry.expires, entry.refreshing);
+    }
+
+    // Brings every entry's expiry forward, as though by passing time
+    fn age(cache: &DnsCache, by: Duration) {
+        for entry in cache.state.lock().unwrap().entries.values_mut() {
+            entry.expires -= by;
+        }
+    }
+
+    fn await_refresh(cache: &DnsCache, host: &str) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while entry(cache, host).1 {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_dns_cache_ttls() {
+        let cache = DnsCache::new()
+            .positive_ttl(Duration::from_secs(60))
+            .negative_ttl(Duration::from_secs(5));
+        let (resolver, inner) = caching(&cache);
+
+        for _ in 0..3 {
+            assert!(
+                resolver.resolve("OK.example", 80).unwrap()
+                    == vec!["127.0.0.1:80".parse().unwrap()]
+            );
+            let err = resolver.resolve("bad.example", 80).unwrap_err();
+            assert!(err.kind() == io::ErrorKind::NotFound);
+        }
+        assert!(inner.lookups.load(Ordering::SeqCst) == 2);
+        assert!(cache.len() == 2);
+
+        // failures expire first
+        age(&cache, Duration::from_secs(10));
+        resolver.resolve("ok.example", 80).unwrap();
+        resolver.resolve("bad.example", 80).unwrap_err();
+        assert!(inner.lookups.load(Ordering::SeqCst) == 3);
+
+        age(&cache, Duration::from_secs(60));
+        resolver.resolve("ok.example", 80).unwrap();
+        assert!(inner.lookups.load(Ordering::SeqCst) == 4);
+
+        cache.flush_host("ok.example");
+        resolver.resolve("ok.example", 80).unwrap();
+        assert!(inner.lookups.load(Ordering::SeqCst) == 5);
+        cache.flush();
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_dns_cache_max_entries() {
+        let cache = DnsCache::new().max_entries(2);
+        let (resolver, inner) = caching(&cache);
+
+        resolver.resolve("ok1", 80).unwrap();
+        age(&cache, Duration::from_secs(1));
+        resolver.resolve("ok2", 80).unwrap();
+        resolver.resolve("ok3", 80).unwrap();
+        assert!(cache.len() == 2);
+
+        // the oldest entry made way
+        resolver.resolve("ok2", 80).unwrap();
+        resolver.resolve("ok3", 80).unwrap();
+        assert!(inner.lookups.load(Ordering::SeqCst) == 3);
+        resolver.resolve("ok1", 80).unwrap();
+        assert!(inner.lookups.load(Ordering::SeqCst) == 4);
+    }
+
+    #[test]
+    fn test_dns_cache_background_refresh() {
+        // refreshing as far ahead as the ttl puts every entry due a refresh as soon as it is used again
+        let cache = DnsCache::new()
+            .positive_ttl(Duration::from_secs(60))
+            .background_refresh(Duration::from_secs(60));
+        let (resolver, inner) = caching(&cache);
+
+        resolver.resolve("ok.example", 80).unwrap();
+        let (expires, _) = entry(&cache, "ok.example");
+
+        // answered from the cache, while a single refresh is started behind it
+        resolver.resolve("ok.example", 80).unwrap();
+        resolver.resolve("ok.example", 80).unwrap();
+        await_refresh(&cache, "ok.example");
+        assert!(inner.lookups.load(Ordering::SeqCst) == 2);
+
+        // the refreshed entry outlives the original
+        assert!(entry(&cache, "ok.example").0 > expires);
+    }
+
+    #[test]
+    fn test_dns_cache_failed_refresh() {
+        // failures aren't cached, which mustn't leave the entry forever refreshing
+        let cache = DnsCache::new()
+            .positive_ttl(Duration::from_secs(60))
+            .negative_ttl(Duration::ZERO)
+            .background_refresh(Duration::from_secs(60));
+        let (resolver, inner) = caching(&cache);
+
+        resolver.resolve("ok.example", 80).unwrap();
+        let (expires, _) = entry(&cache, "ok.example");
+        inner.failing.store(true, Ordering::SeqCst);
+
+        // the cached addresses outlast failed refreshes, each use trying again
+        for lookups in [2, 3] {
+            let addrs = resolver.resolve("ok.example", 80).unwrap();
+            assert!(addrs == vec!["127.0.0.1:80".parse().unwrap()]);
+            await_refresh(&cache, "ok.example");
+            assert!(inner.lookups.load(Ordering::SeqCst) == lookups);
+            assert!(entry(&cache, "ok.example").0 == expires);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use std::{error, fmt, io};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    // Unable to establish a connection with the remote host
+    Connect,
+    // Any other failure reading from or writing to the underlying stream
+    Io,
+    // The response header exceeded the maximum permitted size
+    HeaderTooLarge,
+    // The response header (or a chunk header) could not be parsed
+    MalformedHeader,
+    // The status line was unparseable or carried an out of range status code
+    InvalidStatus,
+    // The response used a transfer encoding other than chunked
+    UnsupportedTransferEncoding,
+    // The response body exceeded the caller supplied limit
+    BodyTooLarge,
+    // A connect, read, write or total timeout elapsed
+    Timeout,
+    // TLS configuration, handshake or certificate verification failure, or https without the tls feature
+    Tls,
+    // A redirect loop was detected, or the redirect policy's limit was exceeded
+    Redirect,
+    // The proxy configuration was invalid, or the proxy refused to open a tunnel
+    Proxy,
+    // A url (or a reference resolved against one, such as a redirect's location) didn't parse
+    InvalidUrl,
+    // No pooled session became available within the pool's acquire timeout
+    PoolTimeout,
+    // A client setting (such as a resolve override) was malformed, or settings were combined which can't be
+    Config,
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::Connect => "connect",
+            ErrorKind::Io => "io",
+            ErrorKind::HeaderTooLarge => "header too large",
+            ErrorKind::MalformedHeader => "malformed header",
+            ErrorKind::InvalidStatus => "invalid status",
+            ErrorKind::UnsupportedTransferEncoding => "unsupported transfer encoding",
+            ErrorKind::BodyTooLarge => "body too large",
+            ErrorKind::Timeout => "timeout",
+            ErrorKind::Tls => "tls",
+            ErrorKind::Redirect => "redirect",
+            ErrorKind::Proxy => "proxy",
+            ErrorKind::InvalidUrl => "invalid url",
+            ErrorKind::PoolTimeout => "pool timeout",
+            ErrorKind::Config => "config",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    kind: ErrorKind,
+    message: String,
+    source: Option<io::Error>,
+}
+
+impl Error {
+    pub fn new(kind: ErrorKind, message: &str) -> Self {
+        Self {
+            kind,
+            message: message.to_owned(),
+            source: None,
+        }
+    }
+
+    pub fn with_source(kind: ErrorKind, message: &str, source: io::Error) -> Self {
+        Self {
+            kind,
+            message: message.to_owned(),
+            source: Some(source),
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        self.kind
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    // Returns the underlying io error, if this error originated from one
+    pub fn io_error(&self) -> Option<&io::Error> {
+        self.source.as_ref()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.kind.as_str(), self.message)?;
+        if let Some(source) = &self.source {
+            write!(f, " ({})", source)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match &self.source {
+            Some(e) => Some(e),
+            None => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        let kind = match err.kind() {
+            // read and write timeouts surface as either of these depending on the platform
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock => ErrorKind::Timeout,
+            _ => ErrorKind::Io,
+        };
+        Self::with_source(kind, "stream operation failed", err)
+    }
+}
+
+// Lets a response be read through std::io, the io error keeps its original kind where there was one
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        let kind = match (&err.source, err.kind) {
+            (Some(source), _) => source.kind(),
+            (None, ErrorKind::Timeout) => io::ErrorKind::TimedOut,
+            (None, ErrorKind::Io) => io::ErrorKind::UnexpectedEof,
+            (None, _) => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::*;
+
+    #[test]
+    fn test_from_io_error() {
+        let err: Error = io::Error::new(io::ErrorKind::ConnectionReset, "reset").into();
+        assert!(err.kind() == ErrorKind::Io);
+        assert!(err.source().is_some());
+
+        let err: Error = io::Error::new(io::ErrorKind::TimedOut, "slow").into();
+        assert!(err.kind() == ErrorKind::Timeout);
+        assert!(err.io_error().unwrap().kind() == io::ErrorKind::TimedOut);
+
+        let err: io::Error = Error::new(ErrorKind::MalformedHeader, "bad chunk").into();
+        assert!(err.kind() == io::ErrorKind::InvalidData);
+        let err: io::Error = Error::new(ErrorKind::Timeout, "slow").into();
+        assert!(err.kind() == io::ErrorKind::TimedOut);
+    }
+}
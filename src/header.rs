@@ -1,9 +1,11 @@
-use std::{
-    collections::{hash_map::Entry, HashMap},
-    error,
-};
+use std::collections::{hash_map::Entry, HashMap};
 
-use crate::{request::Method, response::HttpStatus, url::Url};
+use crate::{
+    error::{Error, ErrorKind},
+    request::Method,
+    response::HttpStatus,
+    url::Url,
+};
 
 #[derive(Clone)]
 pub struct HttpHeader {
@@ -11,6 +13,12 @@ pub struct HttpHeader {
     pub headers: HashMap<String, Vec<String>>,
 }
 
+impl Default for HttpHeader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl HttpHeader {
     pub fn new() -> Self {
         Self {
@@ -55,9 +63,10 @@ impl HttpHeader {
         self.headers.insert(k, vec![v]);
     }
 
-    pub fn from_bytes(b: &[u8]) -> Result<Self, Box<dyn error::Error>> {
+    pub fn from_bytes(b: &[u8]) -> Result<Self, Error> {
         let mut http_header = HttpHeader::new();
-        let header_string = String::from_utf8(b.to_vec())?;
+        let header_string = String::from_utf8(b.to_vec())
+            .map_err(|_| Error::new(ErrorKind::MalformedHeader, "header is not valid utf-8"))?;
         let mut first = true;
         let mut found_newline = false;
         for line in header_string.split("\r\n") {
@@ -67,21 +76,24 @@ impl HttpHeader {
                 continue;
             }
 
-            if line.len() == 0 {
+            if line.is_empty() {
                 found_newline = true;
                 continue;
             }
 
             if found_newline {
-                return Err("malformed header, found data after termination marker".into());
-            }
-
-            let result = line.split_once(":");
-            if result.is_none() {
-                return Err(format!("malformed header line: \"{}\"", line).into());
+                return Err(Error::new(
+                    ErrorKind::MalformedHeader,
+                    "found data after termination marker",
+                ));
             }
 
-            let (k_str, v_str) = result.unwrap();
+            let Some((k_str, v_str)) = line.split_once(":") else {
+                return Err(Error::new(
+                    ErrorKind::MalformedHeader,
+                    &format!("malformed header line: \"{}\"", line),
+                ));
+            };
             let key = k_str.to_lowercase().trim().to_owned();
 
             // we don't support cookies - for a basic HTTP transport system, cookies seem an unnecessary addition
@@ -122,14 +134,21 @@ impl HttpHeader {
         return header_string.as_bytes().to_vec();
     }
 
+    // Returns the header fields alone, each terminated by \r\n, as sent in a chunked body's trailer section
+    pub fn fields_to_vec(&self) -> Vec<u8> {
+        let mut buf: Vec<u8> = vec![];
+        for (key, values) in self.headers.iter() {
+            for value in values {
+                buf.extend_from_slice(format!("{}: {}\r\n", key, value).as_bytes());
+            }
+        }
+        return buf;
+    }
+
     // Returns a single value for key if it exists.  It will always be the first
     // header value received for the given key.
     pub fn get_value(&self, key: &str) -> Option<String> {
-        let v = self.headers.get(&key.to_lowercase());
-        if v.is_none() {
-            return None;
-        }
-
-        return Some(v.unwrap()[0].clone());
+        let v = self.headers.get(&key.to_lowercase())?;
+        return Some(v[0].clone());
     }
 }
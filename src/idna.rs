@@ -0,0 +1,322 @@
+// Internationalized domain names (UTS #46), converting between unicode hostnames and the ascii ones sent on the wire
+// with punycode (RFC 3492).  Without the unicode tables UTS #46 calls for, mapping is limited to lowercasing, the
+// full-width forms of ascii and the alternative full stops, and normalization is left to whoever supplies the name.
+
+const BASE: u32 = 36;
+const T_MIN: u32 = 1;
+const T_MAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 128;
+const ACE_PREFIX: &str = "xn--";
+
+// Converts a hostname to its ascii form, e.g. bücher.example to xn--bcher-kva.example.  Returns None should the
+// name contain a disallowed character, or a label be empty, too long, begin or end with a hyphen, have hyphens in
+// its third and fourth positions once converted, or be an A-label which doesn't decode to a valid label.
+pub fn to_ascii(domain: &str) -> Option<String> {
+    let mapped: String = domain
+        .chars()
+        .map(|c| match c {
+            '\u{3002}' | '\u{ff61}' => '.',
+            // the full-width forms of ascii, the full-width full stop included
+            '\u{ff01}'..='\u{ff5e}' => char::from_u32(c as u32 - 0xfee0).unwrap(),
+            c => c,
+        })
+        .flat_map(char::to_lowercase)
+        .collect();
+    if mapped.chars().any(|c| c != '.' && is_disallowed(c)) {
+        return None;
+    }
+
+    // a trailing dot (the root) is kept as is
+    let (mapped, root) = match mapped.strip_suffix(".") {
+        Some(mapped) => (mapped, "."),
+        None => (mapped.as_str(), ""),
+    };
+
+    let mut labels: Vec<String> = vec![];
+    for label in mapped.split(".") {
+        if label.is_empty() || label.starts_with("-") || label.ends_with("-") {
+            return None;
+        }
+        let ascii = if label.is_ascii() {
+            // an A-label already is ascii, but has to be valid punycode
+            if let Some(encoded) = label.strip_prefix(ACE_PREFIX) {
+                decode(encoded)
+                    .filter(|decoded| !decoded.is_ascii() && is_valid_u_label(decoded))?;
+            }
+            label.to_string()
+        } else if is_valid_u_label(label) {
+            format!("{}{}", ACE_PREFIX, encode(label)?)
+        } else {
+            return None;
+        };
+        if ascii.len() > 63 {
+            return None;
+        }
+        labels.push(ascii);
+    }
+
+    let ascii = labels.join(".");
+    if ascii.len() > 253 {
+        return None;
+    }
+    return Some(ascii + root);
+}
+
+// CheckHyphens for a label which is punycode encoded, the third and fourth positions being reserved for prefixes such
+// as xn--.  Plain ascii labels are left alone, as names like r1---sn-abc.example are in use.
+fn is_valid_u_label(label: &str) -> bool {
+    return !label.starts_with("-")
+        && !label.ends_with("-")
+        && !label.chars().skip(2).take(2).eq("--".chars())
+        && !label.chars().any(is_disallowed);
+}
+
+// Characters a hostname may not contain: ascii other than letters, digits, hyphens and underscores (as for any
+// hostname), along with the control, space, invisible and bidi formatting characters, variation selectors and private
+// use characters which UTS #46 disallows or maps away, and which would let one name pass for another
+fn is_disallowed(c: char) -> bool {
+    if c.is_ascii() {
+        return !(c.is_ascii_alphanumeric() || matches!(c, '-' | '_'));
+    }
+    return c.is_control()
+        || c.is_whitespace()
+        || matches!(
+            c,
+            '\u{ad}'
+                | '\u{34f}'
+                | '\u{61c}'
+                | '\u{180b}'..='\u{180f}'
+                | '\u{200b}'..='\u{200f}'
+                | '\u{202a}'..='\u{202e}'
+                | '\u{2060}'..='\u{206f}'
+                | '\u{fe00}'..='\u{fe0f}'
+                | '\u{feff}'
+                | '\u{fff9}'..='\u{fffb}'
+                | '\u{e000}'..='\u{f8ff}'
+                | '\u{e0000}'..='\u{e0fff}'
+                | '\u{f0000}'..
+        );
+}
+
+// Converts a hostname's A-labels back to unicode for display, leaving any which don't decode as they are
+pub fn to_unicode(domain: &str) -> String {
+    return domain
+        .split(".")
+        .map(|label| {
+            let prefix = label.get(..ACE_PREFIX.len());
+            if !prefix.is_some_and(|p| p.eq_ignore_ascii_case(ACE_PREFIX)) {
+                return label.to_string();
+            }
+            return decode(&label[ACE_PREFIX.len()..]).unwrap_or_else(|| label.to_string());
+        })
+        .collect::<Vec<String>>()
+        .join(".");
+}
+
+// Punycode encodes a label (RFC 3492 section 6.3), None on overflow
+fn encode(input: &str) -> Option<String> {
+    let input: Vec<u32> = input.chars().map(|c| c as u32).collect();
+    let mut output: String = input
+        .iter()
+        .filter(|&&c| c < 0x80)
+        .map(|&c| c as u8 as char)
+        .collect();
+    let basic = output.len() as u32;
+    let mut handled = basic;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    while (handled as usize) < input.len() {
+        let m = input.iter().copied().filter(|&c| c >= n).min()?;
+        delta = delta.checked_add((m - n).checked_mul(handled + 1)?)?;
+        n = m;
+        for &c in &input {
+            if c < n {
+                delta = delta.checked_add(1)?;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = threshold(k, bias);
+                    if q < t {
+                        break;
+                    }
+                    output.push(digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta = delta.checked_add(1)?;
+        n += 1;
+    }
+    return Some(output);
+}
+
+// Decodes a punycode label (RFC 3492 section 6.2), None should it be malformed
+fn decode(input: &str) -> Option<String> {
+    let (basic, extended) = match input.rfind("-") {
+        Some(index) => (&input[..index], &input[index + 1..]),
+        None => ("", input),
+    };
+    if !basic.is_ascii() {
+        return None;
+    }
+
+    let mut output: Vec<char> = basic.chars().collect();
+    let mut n = INITIAL_N;
+    let mut i: u32 = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut digits = extended.bytes().peekable();
+    while digits.peek().is_some() {
+        let old_i = i;
+        let mut w: u32 = 1;
+        let mut k = BASE;
+        loop {
+            let d = digit_value(digits.next()?)?;
+            i = i.checked_add(d.checked_mul(w)?)?;
+            let t = threshold(k, bias);
+            if d < t {
+                break;
+            }
+            w = w.checked_mul(BASE - t)?;
+            k += BASE;
+        }
+        let len = output.len() as u32 + 1;
+        bias = adapt(i - old_i, len, old_i == 0);
+        n = n.checked_add(i / len)?;
+        i %= len;
+        output.insert(i as usize, char::from_u32(n)?);
+        i += 1;
+    }
+    return Some(output.into_iter().collect());
+}
+
+fn threshold(k: u32, bias: u32) -> u32 {
+    if k <= bias {
+        return T_MIN;
+    }
+    return (k - bias).min(T_MAX);
+}
+
+fn adapt(delta: u32, num_points: u32, first_time: bool) -> u32 {
+    let mut delta = if first_time { delta / DAMP } else { delta / 2 };
+    delta += delta / num_points;
+    let mut k = 0;
+    while delta > ((BASE - T_MIN) * T_MAX) / 2 {
+        delta /= BASE - T_MIN;
+        k += BASE;
+    }
+    return k + (BASE - T_MIN + 1) * delta / (delta + SKEW);
+}
+
+fn digit(d: u32) -> char {
+    return match d {
+        0..=25 => (b'a' + d as u8) as char,
+        _ => (b'0' + (d - 26) as u8) as char,
+    };
+}
+
+fn digit_value(b: u8) -> Option<u32> {
+    return match b {
+        b'a'..=b'z' => Some((b - b'a') as u32),
+        b'A'..=b'Z' => Some((b - b'A') as u32),
+        b'0'..=b'9' => Some((b - b'0') as u32 + 26),
+        _ => None,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Including samples from RFC 3492 section 7.1
+    #[test]
+    fn test_punycode() {
+        let cases = [
+            ("bücher", "bcher-kva"),
+            ("münchen", "mnchen-3ya"),
+            ("例え", "r8jz45g"),
+            ("пример", "e1afmkfd"),
+            ("ليهمابتكلموشعربي؟", "egbpdaj6bu4bxfgehfvwxn"),
+            ("他们为什么不说中文", "ihqwcrb4cv8a8dqg056pqjye"),
+            ("3年B組金八先生", "3B-ww4c5e180e575a65lsy2b"),
+            ("MajiでKoiする5秒前", "MajiKoi5-783gue6qz075azm5e"),
+            (
+                "安室奈美恵-with-SUPER-MONKEYS",
+                "-with-SUPER-MONKEYS-pc58ag80a8qai00g7n9n",
+            ),
+        ];
+        for (unicode, punycode) in cases {
+            assert_eq!(encode(unicode).unwrap(), punycode);
+            assert_eq!(decode(punycode).unwrap(), unicode);
+        }
+        assert!(decode("99999999999999999").is_none());
+        assert!(decode("a-é").is_none());
+    }
+
+    #[test]
+    fn test_idna() {
+        assert!(to_ascii("Bücher.example").unwrap() == "xn--bcher-kva.example");
+        assert!(to_ascii("例え。テスト").unwrap() == "xn--r8jz45g.xn--zckzah");
+        assert!(to_ascii("münchen.de.").unwrap() == "xn--mnchen-3ya.de.");
+        assert!(to_ascii("xn--bcher-kva.example").unwrap() == "xn--bcher-kva.example");
+        assert!(to_unicode("xn--bcher-kva.example") == "bücher.example");
+        assert!(to_unicode("XN--R8JZ45G.xn--zckzah") == "例え.テスト");
+        assert!(to_unicode("xn--!!.example") == "xn--!!.example");
+
+        assert!(to_ascii("bücher..example").is_none());
+        assert!(to_ascii("-bücher.example").is_none());
+        assert!(to_ascii(&format!("{}ü.example", "a".repeat(60))).is_none());
+        assert!(to_ascii("xn--abc-.example").is_none());
+    }
+
+    #[test]
+    fn test_idna_full_width() {
+        assert!(to_ascii("ｂüｃｈｅｒ．ｅｘａｍｐｌｅ").unwrap() == "xn--bcher-kva.example");
+        assert!(to_ascii("ＥＸＡＭＰＬＥ.bücher").unwrap() == "example.xn--bcher-kva");
+        // mapping to ascii doesn't let through what the ascii itself wouldn't
+        assert!(to_ascii("bücher！.example").is_none());
+        assert!(to_ascii("bücher／example").is_none());
+    }
+
+    #[test]
+    fn test_idna_disallowed() {
+        for c in [
+            '\u{a0}', '\u{ad}', '\u{200b}', '\u{200d}', '\u{202e}', '\u{2066}', '\u{feff}',
+            '\u{3000}',
+        ] {
+            assert!(to_ascii(&format!("bü{}cher.example", c)).is_none());
+            assert!(to_ascii(&format!("bücher.example{}", c)).is_none());
+        }
+        // an A-label is held to the same rules once decoded, here xn--bcher-kva with a zero width space
+        assert!(to_ascii(&format!(
+            "xn--{}.example",
+            encode("bü\u{200b}cher").unwrap()
+        ))
+        .is_none());
+    }
+
+    #[test]
+    fn test_idna_hyphens() {
+        assert!(to_ascii("bü--cher.example").is_none());
+        assert!(to_ascii("ab--ücher.example").is_none());
+        assert!(to_ascii(&format!("xn--{}.example", encode("ab--ü").unwrap())).is_none());
+
+        // hyphens elsewhere are fine, as are plain ascii labels
+        assert!(to_ascii("büc--her.example").is_some());
+        assert!(to_ascii("bücher.ex--ample").unwrap() == "xn--bcher-kva.ex--ample");
+    }
+}
@@ -1,125 +1,683 @@
 use std::{
     collections::{HashMap, VecDeque},
+    path::PathBuf,
     sync::{
-        mpsc::{channel, Receiver, Sender},
-        Arc, LazyLock, Mutex,
+        mpsc::{channel, Receiver, RecvTimeoutError, Sender},
+        Arc, Condvar, Mutex, Weak,
     },
-    thread::{self, JoinHandle},
+    thread::{self},
     time::{Duration, Instant},
 };
 
-use crate::session::TcpSession;
+use crate::{
+    error::{Error, ErrorKind},
+    session::{Route, TcpSession},
+};
 
-pub static POOL_INSTANCE: LazyLock<Arc<Mutex<SessionPool>>> = LazyLock::new(|| {
-    return Arc::new(Mutex::new(SessionPool::new()));
-});
+// Identifies a set of interchangeable sessions, any session released under a key may be handed out to any request
+// which acquires the same key.
+#[derive(Clone, PartialEq, Eq, Hash, Debug)]
+pub struct PoolKey {
+    pub host: String,
+    pub tls: bool,
+    // the tls configuration the session was secured with (see session::tls_config_id), so that sessions opened with
+    // different roots or client certificates never mix
+    pub tls_config: usize,
+    // caller supplied partition, so that sessions opened on behalf of different tenants or credentials never mix
+    pub tag: Option<String>,
+    // the proxy the session goes through, sessions through different proxies (or none) never mix
+    pub proxy: Option<String>,
+    // the unix socket the session connects to, in place of host
+    pub unix_socket: Option<PathBuf>,
+    // the connector which opened the session, see session::connector_id
+    pub connector: usize,
+}
 
-pub struct SessionPool {
-    host_lookup: HashMap<String, VecDeque<TcpSession>>,
-    kill_chan: Mutex<Option<Sender<bool>>>,
+impl PoolKey {
+    // The server the sessions go to, the host and port or else the socket path, which the per host limits and stats
+    // are counted against whatever the tls, tag, proxy or connector
+    fn server(&self) -> String {
+        return match &self.unix_socket {
+            Some(path) => path.display().to_string(),
+            None => self.host.clone(),
+        };
+    }
+}
+
+// The sessions open to one host, where in use sessions are those handed out and not yet released
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct HostStats {
+    pub idle: usize,
+    pub in_use: usize,
+    pub total: usize,
+}
+
+// A snapshot of a pool, see SessionPool::stats
+#[derive(Clone, Default, Debug)]
+pub struct PoolStats {
+    // keyed by host and port, or the socket path for unix sockets, summed over tls, tags and proxies
+    pub hosts: HashMap<String, HostStats>,
+    // the counts below are cumulative over the pool's lifetime
+    pub opened: u64,
+    // sessions handed out again after being released idle
+    pub reused: u64,
+    // idle sessions closed by the cleanup thread
+    pub expired: u64,
+    // idle sessions closed to stay within the limits, either on release with max_idle_per_host already idle or to make
+    // way for a new session
+    pub evicted: u64,
+    // sessions released or dropped broken, after an operation failed part way
+    pub closed_on_error: u64,
+    // sessions dropped in good order without being released, e.g. along with a response which wasn't released
+    pub dropped: u64,
+    // time spent by requests waiting for the pool, whether or not a session became available
+    pub acquire_wait: Duration,
+}
+
+#[derive(Clone, Copy, Default)]
+struct PoolLimits {
+    max_per_host: Option<usize>,
+    max_total: Option<usize>,
+    max_idle_per_host: Option<usize>,
+    acquire_timeout: Option<Duration>,
+}
+
+struct PoolState {
+    host_lookup: HashMap<PoolKey, VecDeque<TcpSession>>,
+    // dropping the sender (along with the pool state) wakes and terminates the cleanup thread
+    kill_chan: Option<Sender<bool>>,
     last_interaction: Instant,
-    thread_handle: Option<JoinHandle<()>>,
+    limits: PoolLimits,
+    // the number of sessions open to each server (idle or in use, see PoolKey::server), and overall
+    open: HashMap<String, usize>,
+    open_total: usize,
+    // acquires waiting for a session, in the order they arrived
+    waiters: VecDeque<(u64, PoolKey)>,
+    next_ticket: u64,
+    // the cumulative counts, hosts is left empty
+    stats: PoolStats,
 }
 
-impl Drop for SessionPool {
-    fn drop(&mut self) {
-        {
-            let mut kc = self.kill_chan.lock().unwrap();
-            if kc.is_some() {
-                let c = kc.as_mut().unwrap();
-                c.send(true).unwrap();
+struct PoolShared {
+    state: Mutex<PoolState>,
+    // signalled whenever a session is released or closed, which may let a waiting acquire through
+    available: Condvar,
+}
 
-                if let Some(handle) = self.thread_handle.take() {
-                    handle.join().unwrap();
-                }
-            }
+// Counts a session against the pool's limits for as long as it is open.  Dropping the permit (with the session it
+// belongs to) frees its place, so it must never be dropped while the pool state is locked, see PoolState::close.
+pub(crate) struct PoolPermit {
+    key: PoolKey,
+    shared: Weak<PoolShared>,
+    // whether the session was broken when dropped, see TcpSession::drop
+    pub(crate) broken: bool,
+}
+
+impl Drop for PoolPermit {
+    fn drop(&mut self) {
+        let Some(shared) = self.shared.upgrade() else {
+            return;
+        };
+        let mut state = shared.state.lock().unwrap();
+        state.forget(&self.key);
+        if self.broken {
+            state.stats.closed_on_error += 1;
+        } else {
+            state.stats.dropped += 1;
         }
+        drop(state);
+        shared.available.notify_all();
+    }
+}
+
+// A handle to a pool of idle sessions.  Cloning the handle shares the underlying pool, which is how several clients
+// can be made to draw from the same set of connections.
+#[derive(Clone)]
+pub struct SessionPool {
+    shared: Arc<PoolShared>,
+}
+
+impl Default for SessionPool {
+    fn default() -> Self {
+        Self::new()
     }
 }
 
 impl SessionPool {
+    // Creates a pool without limits, which opens as many sessions as are asked of it
     pub fn new() -> Self {
         Self {
-            host_lookup: HashMap::new(),
-            kill_chan: Mutex::new(None),
-            last_interaction: Instant::now(),
-            thread_handle: None,
+            shared: Arc::new(PoolShared {
+                state: Mutex::new(PoolState {
+                    host_lookup: HashMap::new(),
+                    kill_chan: None,
+                    last_interaction: Instant::now(),
+                    limits: PoolLimits::default(),
+                    open: HashMap::new(),
+                    open_total: 0,
+                    waiters: VecDeque::new(),
+                    next_ticket: 0,
+                    stats: PoolStats::default(),
+                }),
+                available: Condvar::new(),
+            }),
         }
     }
 
-    pub fn acquire(&mut self, host: &String) -> TcpSession {
-        self.last_interaction = Instant::now();
-        let sessions_opt = self.host_lookup.get_mut(host);
-        if sessions_opt.is_some() {
-            let sessions = sessions_opt.unwrap();
-            let session = sessions.pop_front().unwrap();
-            return session;
-        }
+    // Limits the sessions open to any one host, idle or in use, beyond which requests wait for one to be released.
+    // Sessions count against the host whichever client, tag or proxy they were opened for, and an idle session
+    // opened for another is closed to make way should there be any.
+    pub fn max_connections_per_host(self, max: usize) -> Self {
+        self.shared.state.lock().unwrap().limits.max_per_host = Some(max);
+        self
+    }
+
+    // Limits the sessions open across all hosts.  Once reached, an idle session to another host is closed to make way
+    // for a new one, should there be any, otherwise requests wait for one to be released.
+    pub fn max_connections(self, max: usize) -> Self {
+        self.shared.state.lock().unwrap().limits.max_total = Some(max);
+        self
+    }
+
+    // Limits the idle sessions kept for any one host (however partitioned), sessions released beyond it are closed
+    pub fn max_idle_per_host(self, max: usize) -> Self {
+        self.shared.state.lock().unwrap().limits.max_idle_per_host = Some(max);
+        self
+    }
+
+    // How long a request waits for a session when the pool is at its limits before failing with
+    // ErrorKind::PoolTimeout.  Without it requests wait for as long as it takes.
+    pub fn acquire_timeout(self, timeout: Duration) -> Self {
+        self.shared.state.lock().unwrap().limits.acquire_timeout = Some(timeout);
+        self
+    }
 
-        return TcpSession::new(host.clone());
+    // Hands out an idle session for the key, or a new one should there be none.  When the pool is at its limits the
+    // caller waits its turn, first come first served among those the pool could serve.
+    pub(crate) fn acquire(&self, key: &PoolKey, route: &Route) -> Result<TcpSession, Error> {
+        let started = Instant::now();
+        let mut state = self.shared.state.lock().unwrap();
+        state.last_interaction = Instant::now();
+        let ticket = state.next_ticket;
+        state.next_ticket += 1;
+        state.waiters.push_back((ticket, key.clone()));
+        let deadline = state
+            .limits
+            .acquire_timeout
+            .map(|timeout| Instant::now() + timeout);
+
+        loop {
+            if state.first_servable() == Some(ticket) {
+                state.waiters.retain(|(t, _)| *t != ticket);
+                let session = state.take(key, route, &self.shared);
+                state.stats.acquire_wait += started.elapsed();
+                // whoever is next in line may be servable too
+                self.shared.available.notify_all();
+                return Ok(session);
+            }
+
+            state = match deadline {
+                None => self.shared.available.wait(state).unwrap(),
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        state.waiters.retain(|(t, _)| *t != ticket);
+                        state.stats.acquire_wait += started.elapsed();
+                        self.shared.available.notify_all();
+                        return Err(Error::new(
+                            ErrorKind::PoolTimeout,
+                            &format!("no session to {} became available in time", key.host),
+                        ));
+                    }
+                    self.shared
+                        .available
+                        .wait_timeout(state, deadline - now)
+                        .unwrap()
+                        .0
+                }
+            };
+        }
     }
 
-    pub fn release(&mut self, session: TcpSession) {
-        self.last_interaction = Instant::now();
+    pub(crate) fn release(&self, session: TcpSession) {
+        // a session which failed part way through an exchange is closed rather than reused
+        if session.is_broken() {
+            return;
+        }
+
+        let mut state = self.shared.state.lock().unwrap();
+        state.last_interaction = Instant::now();
         let mut s = session;
         s.set_idle();
 
-        let sessions_opt = self.host_lookup.get_mut(&s.host);
-        if sessions_opt.is_some() {
-            let sessions = sessions_opt.unwrap();
+        let idle = state.idle_to(&s.key.server());
+        if state
+            .limits
+            .max_idle_per_host
+            .is_some_and(|max| idle >= max)
+        {
+            state.stats.evicted += 1;
+            state.close(s);
+            drop(state);
+            self.shared.available.notify_all();
+            return;
+        }
+
+        if let Some(sessions) = state.host_lookup.get_mut(&s.key) {
             sessions.push_back(s);
+            drop(state);
+            self.shared.available.notify_all();
             return;
         }
 
-        let host = s.host.clone();
+        let key = s.key.clone();
         let mut vd = VecDeque::new();
         vd.push_back(s);
-        self.host_lookup.insert(host, vd);
+        state.host_lookup.insert(key, vd);
 
         // when an item is released to the session pool, we must ensure the cleanup thread is running, which will run while there are items \
         // to be cleaned up (plus a fixed amount of time)
-        let mut kc_guard = self.kill_chan.lock().unwrap();
-        if kc_guard.is_none() {
+        if state.kill_chan.is_none() {
             let (tx, rx): (Sender<bool>, Receiver<bool>) = channel();
-            *kc_guard = Some(tx);
-            let pool_inst = POOL_INSTANCE.clone();
-            self.thread_handle = Some(thread::spawn(move || {
-                loop {
-                    match rx.recv_timeout(Duration::from_secs(5)) {
-                        Ok(_) => return,
-
-                        Err(_) => {
-                            // timeout received, perform empty check, etc.
-                            let mut pi = pool_inst.lock().unwrap();
-                            let thread_ending = pi.remove_expired();
-                            if thread_ending {
-                                let mut guard = pi.kill_chan.lock().unwrap();
-                                *guard = None;
-                            }
-                            return;
-                        }
-                    }
-                }
-            }));
+            state.kill_chan = Some(tx);
+            let weak_shared = Arc::downgrade(&self.shared);
+            thread::spawn(move || cleanup(weak_shared, rx));
+        }
+        drop(state);
+        self.shared.available.notify_all();
+    }
+
+    // Closes all idle sessions held by the pool.  Sessions currently in use are unaffected, and may still be released
+    // back to the pool afterwards.
+    pub fn clear(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+        let idle: Vec<TcpSession> = state
+            .host_lookup
+            .drain()
+            .flat_map(|(_, sessions)| sessions)
+            .collect();
+        for session in idle {
+            state.close(session);
+        }
+        drop(state);
+        self.shared.available.notify_all();
+    }
+
+    // Takes a snapshot of the sessions open to each host, and of the pool's cumulative counts
+    pub fn stats(&self) -> PoolStats {
+        let state = self.shared.state.lock().unwrap();
+        let mut stats = state.stats.clone();
+        for (server, open) in &state.open {
+            let idle = state.idle_to(server);
+            let host_stats = HostStats {
+                idle,
+                in_use: open - idle,
+                total: *open,
+            };
+            stats.hosts.insert(server.clone(), host_stats);
+        }
+        return stats;
+    }
+}
+
+#[cfg(test)]
+impl SessionPool {
+    // Blocks until n requests are waiting their turn, so that tests needn't guess how long getting there takes
+    pub(crate) fn await_waiters(&self, n: usize) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while self.shared.state.lock().unwrap().waiters.len() < n {
+            assert!(Instant::now() < deadline);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+}
+
+impl PoolState {
+    // The first waiter the pool can serve right away, either from its idle sessions or by opening another
+    fn first_servable(&self) -> Option<u64> {
+        let idle_elsewhere = self
+            .host_lookup
+            .values()
+            .any(|sessions| !sessions.is_empty());
+        for (ticket, key) in &self.waiters {
+            if self
+                .host_lookup
+                .get(key)
+                .is_some_and(|sessions| !sessions.is_empty())
+            {
+                return Some(*ticket);
+            }
+            // an idle session to the server under another key can be closed to make way
+            let server = key.server();
+            let open = self.open.get(&server).copied().unwrap_or(0);
+            let host_ok = self
+                .limits
+                .max_per_host
+                .is_none_or(|max| open < max || self.idle_to(&server) > 0);
+            let total_ok = self
+                .limits
+                .max_total
+                .is_none_or(|max| self.open_total < max || idle_elsewhere);
+            if host_ok && total_ok {
+                return Some(*ticket);
+            }
+        }
+        return None;
+    }
+
+    // Pops an idle session for the key, or opens a new one, closing the longest idle session to the same server (under
+    // another key) or else to another server first should the pool be at its limits.  Only called for a waiter
+    // first_servable picked.
+    fn take(&mut self, key: &PoolKey, route: &Route, shared: &Arc<PoolShared>) -> TcpSession {
+        if let Some(session) = self
+            .host_lookup
+            .get_mut(key)
+            .and_then(|sessions| sessions.pop_front())
+        {
+            self.stats.reused += 1;
+            return session;
+        }
+
+        let server = key.server();
+        let open = self.open.get(&server).copied().unwrap_or(0);
+        if self.limits.max_per_host.is_some_and(|max| open >= max) {
+            self.close_oldest_idle(|k| k.server() == server);
+        }
+        if self
+            .limits
+            .max_total
+            .is_some_and(|max| self.open_total >= max)
+        {
+            self.close_oldest_idle(|_| true);
+        }
+
+        *self.open.entry(server).or_insert(0) += 1;
+        self.open_total += 1;
+        self.stats.opened += 1;
+        let mut session = TcpSession::new(key.clone(), route.clone());
+        session.permit = Some(PoolPermit {
+            key: key.clone(),
+            shared: Arc::downgrade(shared),
+            broken: false,
+        });
+        return session;
+    }
+
+    // The idle sessions to a server, summed over its keys
+    fn idle_to(&self, server: &str) -> usize {
+        return self
+            .host_lookup
+            .iter()
+            .filter(|(key, _)| key.server() == server)
+            .map(|(_, sessions)| sessions.len())
+            .sum();
+    }
+
+    // Closes the session which has been idle longest among the keys matching filter, if any
+    fn close_oldest_idle(&mut self, filter: impl Fn(&PoolKey) -> bool) {
+        let oldest = self
+            .host_lookup
+            .iter()
+            .filter(|(k, _)| filter(k))
+            .filter_map(|(k, sessions)| sessions.front().map(|s| (k.clone(), s.idle_from())))
+            .min_by_key(|(_, idle_from)| *idle_from)
+            .map(|(k, _)| k);
+        if let Some(session) = oldest.and_then(|k| self.host_lookup.get_mut(&k)?.pop_front()) {
+            self.stats.evicted += 1;
+            self.close(session);
+        }
+    }
+
+    // Closes a session while the state is locked, freeing its place without its permit locking the state again
+    fn close(&mut self, mut session: TcpSession) {
+        if let Some(mut permit) = session.permit.take() {
+            self.forget(&permit.key);
+            // leaves the permit nothing to do once dropped
+            permit.shared = Weak::new();
+        }
+    }
+
+    fn forget(&mut self, key: &PoolKey) {
+        let server = key.server();
+        if let Some(open) = self.open.get_mut(&server) {
+            *open -= 1;
+            if *open == 0 {
+                self.open.remove(&server);
+            }
+            self.open_total -= 1;
         }
     }
 
     // removes any expired items and returns true if there are no items left
-    pub fn remove_expired(&mut self) -> bool {
+    fn remove_expired(&mut self) -> bool {
         let now = Instant::now();
-        let mut rem_hosts: Vec<String> = vec![];
-        for (host, sessions) in self.host_lookup.iter_mut() {
-            sessions.retain(|x| x.is_expired(&now));
-            if sessions.len() == 0 {
-                rem_hosts.push(host.clone());
+        let mut expired: Vec<TcpSession> = vec![];
+        self.host_lookup.retain(|_, sessions| {
+            let (stale, fresh): (VecDeque<TcpSession>, VecDeque<TcpSession>) =
+                sessions.drain(..).partition(|x| x.is_expired(&now));
+            expired.extend(stale);
+            *sessions = fresh;
+            !sessions.is_empty()
+        });
+        for session in expired {
+            self.stats.expired += 1;
+            self.close(session);
+        }
+
+        return self.host_lookup.is_empty()
+            && now.duration_since(self.last_interaction).as_secs() > 30;
+    }
+}
+
+// The cleanup thread only holds a weak reference so that it never keeps a pool alive which has been dropped by all of
+// its clients.
+fn cleanup(weak_shared: Weak<PoolShared>, rx: Receiver<bool>) {
+    loop {
+        match rx.recv_timeout(Duration::from_secs(5)) {
+            Err(RecvTimeoutError::Timeout) => {
+                // timeout received, perform empty check, etc.
+                let Some(shared) = weak_shared.upgrade() else {
+                    return;
+                };
+                let mut st = shared.state.lock().unwrap();
+                let done = st.remove_expired();
+                if done {
+                    st.kill_chan = None;
+                }
+                drop(st);
+                shared.available.notify_all();
+                if done {
+                    return;
+                }
             }
+
+            _ => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        connector::DEFAULT_CONNECTOR, resolver::DEFAULT_RESOLVER, session::StreamSecurity,
+    };
+
+    fn key(host: &str) -> PoolKey {
+        return PoolKey {
+            host: host.to_string(),
+            tls: false,
+            tls_config: 0,
+            tag: None,
+            proxy: None,
+            unix_socket: None,
+            connector: 0,
+        };
+    }
+
+    // sessions only connect once used, so none of these touch the network
+    fn route() -> Route {
+        return Route {
+            security: StreamSecurity::Plain,
+            proxy: None,
+            connector: DEFAULT_CONNECTOR.clone(),
+            resolver: DEFAULT_RESOLVER.clone(),
+        };
+    }
+
+    #[test]
+    fn test_acquire_fifo() {
+        let pool = SessionPool::new().max_connections_per_host(1);
+        let held = pool.acquire(&key("a"), &route()).unwrap();
+
+        let order = Arc::new(Mutex::new(vec![]));
+        let mut handles = vec![];
+        for i in 0..3 {
+            let (waiter_pool, order) = (pool.clone(), order.clone());
+            handles.push(thread::spawn(move || {
+                let session = waiter_pool.acquire(&key("a"), &route()).unwrap();
+                order.lock().unwrap().push(i);
+                drop(session);
+            }));
+            pool.await_waiters(i + 1);
         }
 
-        for host in rem_hosts {
-            self.host_lookup.remove(&host);
+        // another host isn't held up behind them
+        drop(pool.acquire(&key("b"), &route()).unwrap());
+
+        drop(held);
+        for h in handles {
+            h.join().unwrap();
         }
+        assert!(*order.lock().unwrap() == vec![0, 1, 2]);
+    }
 
-        return self.host_lookup.len() == 0
-            && now.duration_since(self.last_interaction).as_secs() > 30;
+    #[test]
+    fn test_max_connections() {
+        let pool = SessionPool::new()
+            .max_connections(1)
+            .acquire_timeout(Duration::from_millis(50));
+
+        // an idle session to another host makes way
+        let a = pool.acquire(&key("a"), &route()).unwrap();
+        pool.release(a);
+        let b = pool.acquire(&key("b"), &route()).unwrap();
+        assert!(pool
+            .shared
+            .state
+            .lock()
+            .unwrap()
+            .host_lookup
+            .values()
+            .all(|s| s.is_empty()));
+
+        let err = pool.acquire(&key("a"), &route()).err().unwrap();
+        assert!(err.kind() == ErrorKind::PoolTimeout);
+        drop(b);
+        drop(pool.acquire(&key("a"), &route()).unwrap());
+    }
+
+    #[test]
+    fn test_host_limits_across_keys() {
+        let pool = SessionPool::new()
+            .max_connections_per_host(2)
+            .max_idle_per_host(1)
+            .acquire_timeout(Duration::from_millis(50));
+        let tagged = |tag: &str| {
+            let mut key = key("a");
+            key.tag = Some(tag.to_string());
+            return key;
+        };
+
+        // however the sessions are partitioned, they all count against the host
+        let x = pool.acquire(&tagged("x"), &route()).unwrap();
+        let y = pool.acquire(&tagged("y"), &route()).unwrap();
+        let err = pool.acquire(&tagged("z"), &route()).err().unwrap();
+        assert!(err.kind() == ErrorKind::PoolTimeout);
+        assert!(pool.stats().hosts["a"].total == 2);
+
+        // only one of them is kept idle
+        pool.release(x);
+        pool.release(y);
+        assert!(pool.stats().hosts["a"].idle == 1);
+
+        // and an idle session under another tag makes way for a new one
+        let z = pool.acquire(&tagged("z"), &route()).unwrap();
+        let w = pool.acquire(&tagged("w"), &route()).unwrap();
+        let stats = pool.stats();
+        assert!(stats.hosts["a"].idle == 0);
+        assert!(stats.hosts["a"].total == 2);
+        assert!(stats.opened == 4);
+        drop((z, w));
+    }
+
+    #[test]
+    fn test_pool_stats() {
+        let pool = SessionPool::new()
+            .max_connections_per_host(2)
+            .max_idle_per_host(1)
+            .acquire_timeout(Duration::from_millis(50));
+
+        let a = pool.acquire(&key("a"), &route()).unwrap();
+        let b = pool.acquire(&key("a"), &route()).unwrap();
+        let mut unix_key = key("localhost");
+        unix_key.unix_socket = Some(PathBuf::from("/run/api.sock"));
+        let c = pool.acquire(&unix_key, &route()).unwrap();
+        assert!(pool.acquire(&key("a"), &route()).is_err());
+
+        let stats = pool.stats();
+        assert!(
+            stats.hosts["a"]
+                == HostStats {
+                    idle: 0,
+                    in_use: 2,
+                    total: 2
+                }
+        );
+        assert!(
+            stats.hosts["/run/api.sock"]
+                == HostStats {
+                    idle: 0,
+                    in_use: 1,
+                    total: 1
+                }
+        );
+        assert!(stats.opened == 3);
+        assert!(stats.acquire_wait >= Duration::from_millis(50));
+
+        // the second idle session is closed, and the broken one isn't kept
+        pool.release(a);
+        pool.release(b);
+        let mut a = pool.acquire(&key("a"), &route()).unwrap();
+        a.set_broken();
+        pool.release(a);
+        let stats = pool.stats();
+        assert!(stats.reused == 1 && stats.evicted == 1);
+        assert!(stats.closed_on_error == 1 && stats.dropped == 0);
+
+        // dropping a session without releasing it only counts as an error when it is broken
+        drop(c);
+        let stats = pool.stats();
+        assert!(stats.closed_on_error == 1 && stats.dropped == 1);
+        let mut d = pool.acquire(&key("a"), &route()).unwrap();
+        d.set_broken();
+        drop(d);
+
+        let stats = pool.stats();
+        assert!(stats.hosts.is_empty());
+        assert!(stats.opened == 4);
+        assert!(stats.closed_on_error == 2 && stats.dropped == 1);
+
+        // an idle session closed to make way for one under another tag is evicted too
+        let e = pool.acquire(&key("a"), &route()).unwrap();
+        let f = pool.acquire(&key("a"), &route()).unwrap();
+        pool.release(e);
+        let mut tagged = key("a");
+        tagged.tag = Some("t".to_string());
+        let g = pool.acquire(&tagged, &route()).unwrap();
+        let stats = pool.stats();
+        assert!(stats.evicted == 2 && stats.opened == 7);
+        assert!(stats.hosts["a"].idle == 0 && stats.hosts["a"].total == 2);
+        drop((f, g));
     }
 }
@@ -0,0 +1,139 @@
+use std::sync::Arc;
+
+use crate::{
+    error::{Error, ErrorKind},
+    request::{Method, Request},
+    response::Response,
+    url::Url,
+};
+
+// Describes a redirect about to be followed, as passed to a custom redirect policy
+pub struct RedirectAttempt<'a> {
+    pub status: usize,
+    // the url which answered with the redirect
+    pub url: &'a Url,
+    // the url being redirected to
+    pub location: &'a Url,
+    // the urls already redirected away from, earliest first
+    pub previous: &'a [Url],
+}
+
+#[derive(Clone)]
+pub enum RedirectPolicy {
+    // redirect responses are returned to the caller as is
+    None,
+    // up to n redirects are followed, after which the request fails with ErrorKind::Redirect
+    Limited(usize),
+    // the closure decides whether each redirect is followed, a redirect which isn't followed is returned as is
+    Custom(Arc<dyn Fn(&RedirectAttempt) -> bool + Send + Sync>),
+}
+
+impl RedirectPolicy {
+    pub fn custom(f: impl Fn(&RedirectAttempt) -> bool + Send + Sync + 'static) -> Self {
+        return RedirectPolicy::Custom(Arc::new(f));
+    }
+}
+
+pub struct Redirect {
+    pub request: Request,
+    // whether the request body is to be sent again with the redirected request
+    pub keep_body: bool,
+}
+
+// Returns the request to send next, should the response be a redirect which the policy permits following.  visited
+// holds every request sent so far (including req), and is used to detect redirect loops.
+pub fn next_request(
+    policy: &RedirectPolicy,
+    req: &Request,
+    resp: &Response,
+    visited: &[(Method, Url)],
+) -> Result<Option<Redirect>, Error> {
+    let status = resp.status.status_code;
+    if !matches!(status, 301 | 302 | 303 | 307 | 308) || matches!(policy, RedirectPolicy::None) {
+        return Ok(None);
+    }
+
+    // a location which doesn't parse leaves the redirect to the caller, as does one which is missing
+    let Some(location) = resp
+        .header
+        .get_value("location")
+        .and_then(|location| req.url.join(&location).ok())
+    else {
+        return Ok(None);
+    };
+
+    let previous: Vec<Url> = visited[..visited.len() - 1]
+        .iter()
+        .map(|(_, url)| url.clone())
+        .collect();
+    match policy {
+        RedirectPolicy::None => return Ok(None),
+        RedirectPolicy::Limited(max) => {
+            if previous.len() >= *max {
+                return Err(Error::new(
+                    ErrorKind::Redirect,
+                    &format!("exceeded the limit of {} redirects", max),
+                ));
+            }
+        }
+        RedirectPolicy::Custom(f) => {
+            let attempt = RedirectAttempt {
+                status,
+                url: &req.url,
+                location: &location,
+                previous: &previous,
+            };
+            if !f(&attempt) {
+                return Ok(None);
+            }
+        }
+    }
+
+    // 303 always switches to GET, as does a POST answered with 301 or 302 (as every browser does).  Only 307 and 308
+    // guarantee that the method is preserved, but whenever it is the body goes with it, as a PUT sent on without its
+    // body would overwrite the resource with nothing.
+    let method = match (status, req.method) {
+        (303, Method::Head) => Method::Head,
+        (303, _) | (301 | 302, Method::Post) => Method::Get,
+        (_, method) => method,
+    };
+    let keep_body = method == req.method;
+
+    if visited
+        .iter()
+        .any(|(m, url)| *m == method && same_resource(url, &location))
+    {
+        return Err(Error::new(
+            ErrorKind::Redirect,
+            &format!("redirect loop detected at {}", location),
+        ));
+    }
+
+    let mut header = req.header.clone();
+    if !keep_body {
+        header.headers.remove("content-type");
+    }
+
+    // credentials are only ever sent to the host they were intended for
+    if location.scheme() != req.url.scheme()
+        || location.effective_host() != req.url.effective_host()
+    {
+        header.headers.remove("authorization");
+    }
+
+    let mut request = Request::new(method, location);
+    request.header = header;
+    request.timeouts = req.timeouts;
+    // a socket override only applies to the host it was given for
+    if request.url.effective_host() == req.url.effective_host() {
+        request.unix_socket = req.unix_socket.clone();
+    }
+    return Ok(Some(Redirect { request, keep_body }));
+}
+
+// Urls which differ only by fragment identify the same resource
+fn same_resource(a: &Url, b: &Url) -> bool {
+    return a.scheme() == b.scheme()
+        && a.effective_host() == b.effective_host()
+        && a.resource() == b.resource();
+}
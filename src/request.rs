@@ -1,5 +1,8 @@
-use crate::{header::HttpHeader, url::Url};
+use std::path::PathBuf;
 
+use crate::{header::HttpHeader, timeout::Timeouts, url::Url};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
 pub enum Method {
     Get,
     Post,
@@ -20,22 +23,33 @@ impl Method {
             Method::Delete => "DELETE",
         }
     }
+
+    // Idempotent requests may safely be sent more than once
+    pub fn is_idempotent(&self) -> bool {
+        !matches!(self, Method::Post | Method::Patch)
+    }
 }
 
 pub struct Request {
     pub method: Method,
     pub header: HttpHeader,
     pub url: Url,
+    // overrides the client's timeouts for this request only
+    pub timeouts: Timeouts,
+    // connects to this unix socket rather than to the url's host, which is still sent as the host header
+    pub unix_socket: Option<PathBuf>,
 }
 
 impl Request {
+    // The request line is derived from the method and url when the request is sent, so either may be changed
+    // beforehand.  Only the header's fields are sent from header.
     pub fn new(method: Method, url: Url) -> Self {
-        let mut header = HttpHeader::new();
-        header.set_req_line(&method, &url);
         return Request {
             method,
-            header,
+            header: HttpHeader::new(),
             url,
+            timeouts: Timeouts::new(),
+            unix_socket: None,
         };
     }
 }
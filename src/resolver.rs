@@ -0,0 +1,169 @@
+use std::{
+    collections::HashMap,
+    io,
+    net::{IpAddr, Ipv6Addr, SocketAddr, SocketAddrV6, ToSocketAddrs},
+    sync::{Arc, LazyLock},
+};
+
+use crate::error::{Error, ErrorKind};
+
+// Resolves hostnames to the addresses connections are attempted to, in order of preference
+pub trait Resolver: Send + Sync {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>>;
+}
+
+// Resolves using the system resolver (getaddrinfo), the default
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemResolver;
+
+impl Resolver for SystemResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        // a zone id given as an interface index needs no lookup, an interface name is left to getaddrinfo
+        if let Some((address, zone)) = host.split_once("%") {
+            if let (Ok(address), Ok(scope_id)) = (address.parse::<Ipv6Addr>(), zone.parse::<u32>())
+            {
+                return Ok(vec![SocketAddrV6::new(address, port, 0, scope_id).into()]);
+            }
+        }
+        return Ok((host, port).to_socket_addrs()?.collect());
+    }
+}
+
+// Pins host and port pairs to fixed addresses, much like curl's --resolve, and hands everything else to a fallback
+// resolver
+#[derive(Clone)]
+pub struct StaticResolver {
+    overrides: HashMap<(String, u16), Vec<SocketAddr>>,
+    fallback: Arc<dyn Resolver>,
+}
+
+impl Default for StaticResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StaticResolver {
+    // Falls back to the system resolver
+    pub fn new() -> Self {
+        Self::with_fallback(DEFAULT_RESOLVER.clone())
+    }
+
+    pub fn with_fallback(fallback: Arc<dyn Resolver>) -> Self {
+        Self {
+            overrides: HashMap::new(),
+            fallback,
+        }
+    }
+
+    // Resolves host and port to addr, in addition to any addresses it is already pinned to
+    pub fn add(&mut self, host: &str, port: u16, addr: IpAddr) {
+        self.overrides
+            .entry((host.to_lowercase(), port))
+            .or_default()
+            .push(SocketAddr::new(addr, port));
+    }
+
+    // Adds an override in curl's --resolve format, "host:port:addr[,addr]...", where an ipv6 address may be
+    // bracketed
+    pub fn add_str(&mut self, entry: &str) -> Result<(), Error> {
+        let invalid = || {
+            Error::new(
+                ErrorKind::Config,
+                &format!("invalid resolve override: \"{}\"", entry),
+            )
+        };
+
+        let mut parts = entry.splitn(3, ":");
+        let (Some(host), Some(port), Some(addrs)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let port = port.parse::<u16>().map_err(|_| invalid())?;
+        if host.is_empty() {
+            return Err(invalid());
+        }
+
+        for addr in addrs.split(",") {
+            let addr = addr.trim().trim_start_matches("[").trim_end_matches("]");
+            let addr = addr.parse::<IpAddr>().map_err(|_| invalid())?;
+            self.add(host, port, addr);
+        }
+        return Ok(());
+    }
+}
+
+impl Resolver for StaticResolver {
+    fn resolve(&self, host: &str, port: u16) -> io::Result<Vec<SocketAddr>> {
+        if let Some(addrs) = self.overrides.get(&(host.to_lowercase(), port)) {
+            return Ok(addrs.clone());
+        }
+        return self.fallback.resolve(host, port);
+    }
+}
+
+pub(crate) static DEFAULT_RESOLVER: LazyLock<Arc<dyn Resolver>> =
+    LazyLock::new(|| Arc::new(SystemResolver));
+
+// Splits an authority into host and port, removing the brackets from an ipv6 literal and decoding its zone id (if any)
+pub(crate) fn split_authority(authority: &str) -> Option<(String, u16)> {
+    let (host, port) = authority.rsplit_once(":")?;
+    let port = port.parse::<u16>().ok()?;
+    let host = match host.strip_prefix("[").and_then(|h| h.strip_suffix("]")) {
+        Some(literal) => literal.replacen("%25", "%", 1),
+        None => host.to_string(),
+    };
+    return Some((host, port));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_static_resolver() {
+        let mut resolver = StaticResolver::new();
+        resolver.add_str("Example.com:443:10.0.0.1,[::1]").unwrap();
+        resolver.add("example.com", 80, "10.0.0.2".parse().unwrap());
+
+        let addrs = resolver.resolve("example.COM", 443).unwrap();
+        assert!(
+            addrs
+                == vec![
+                    "10.0.0.1:443".parse().unwrap(),
+                    "[::1]:443".parse().unwrap()
+                ]
+        );
+        assert!(
+            resolver.resolve("example.com", 80).unwrap() == vec!["10.0.0.2:80".parse().unwrap()]
+        );
+
+        // anything not pinned goes to the fallback
+        let addrs = resolver.resolve("127.0.0.1", 8080).unwrap();
+        assert!(addrs == vec!["127.0.0.1:8080".parse().unwrap()]);
+
+        for entry in [
+            "example.com:443",
+            "example.com:https:10.0.0.1",
+            "example.com:443:not-an-ip",
+        ] {
+            assert!(resolver.add_str(entry).unwrap_err().kind() == ErrorKind::Config);
+        }
+    }
+
+    #[test]
+    fn test_split_authority() {
+        assert!(split_authority("example.com:80") == Some(("example.com".to_string(), 80)));
+        assert!(split_authority("[::1]:443") == Some(("::1".to_string(), 443)));
+        assert!(split_authority("[fe80::1%25eth0]:80") == Some(("fe80::1%eth0".to_string(), 80)));
+        assert!(split_authority("example.com").is_none());
+        assert!(split_authority("[::1]").is_none());
+    }
+
+    #[test]
+    fn test_scoped_address() {
+        let addrs = SystemResolver.resolve("fe80::1%3", 80).unwrap();
+        assert!(addrs == vec![SocketAddrV6::new("fe80::1".parse().unwrap(), 80, 0, 3).into()]);
+        assert!(addrs[0].to_string() == "[fe80::1%3]:80");
+    }
+}
@@ -1,6 +1,16 @@
-use std::error;
+use std::{
+    fmt,
+    io::{self, BufRead, Read},
+    net::SocketAddr,
+};
 
-use crate::{header::HttpHeader, session::TcpSession};
+use crate::{
+    error::{Error, ErrorKind},
+    header::HttpHeader,
+    session::TcpSession,
+    url::Url,
+    MAX_HEADER_SIZE,
+};
 
 pub struct HttpStatus {
     pub proto: String,
@@ -10,20 +20,18 @@ pub struct HttpStatus {
 
 impl HttpStatus {
     pub fn new(status_code: usize) -> Self {
-        let status_msg: &str;
-
         // We are a client, this is implemented for testing purposes only
-        if status_code < 200 {
-            status_msg = "Information";
+        let status_msg = if status_code < 200 {
+            "Information"
         } else if status_code < 300 {
-            status_msg = "OK";
+            "OK"
         } else if status_code < 400 {
-            status_msg = "Redirect";
+            "Redirect"
         } else if status_code < 500 {
-            status_msg = "Client error";
+            "Client error"
         } else {
-            status_msg = "Server error";
-        }
+            "Server error"
+        };
 
         Self {
             proto: "HTTP/1.1".to_owned(),
@@ -32,22 +40,30 @@ impl HttpStatus {
         }
     }
 
-    pub fn to_string(&self) -> String {
-        return format!("{} {} {}", self.proto, self.status_code, self.status_msg);
-    }
-
-    pub fn from_string(status: &String) -> Result<Self, Box<dyn error::Error>> {
-        let parts: Vec<&str> = status.split(" ").collect();
-        if parts.len() != 3 {
-            return Err("unable to parse http status header".into());
+    pub fn from_string(status: &str) -> Result<Self, Error> {
+        // the reason phrase may itself contain spaces, or be omitted entirely
+        let parts: Vec<&str> = status.splitn(3, " ").collect();
+        if parts.len() < 2 {
+            return Err(Error::new(
+                ErrorKind::InvalidStatus,
+                "unable to parse http status header",
+            ));
         }
 
         let proto = parts[0].to_string();
-        let status_code: usize = parts[1].to_string().parse::<usize>()?;
-        if status_code < 100 || status_code >= 600 {
-            return Err(format!("invalid status code: {}", status_code).into());
+        let status_code = parts[1].parse::<usize>().map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidStatus,
+                &format!("invalid status code: {}", parts[1]),
+            )
+        })?;
+        if !(100..600).contains(&status_code) {
+            return Err(Error::new(
+                ErrorKind::InvalidStatus,
+                &format!("invalid status code: {}", status_code),
+            ));
         }
-        let status_msg = parts[2].to_string();
+        let status_msg = parts.get(2).unwrap_or(&"").to_string();
 
         return Ok(HttpStatus {
             proto,
@@ -57,59 +73,76 @@ impl HttpStatus {
     }
 }
 
+impl fmt::Display for HttpStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} {}", self.proto, self.status_code, self.status_msg)
+    }
+}
+
 pub struct Response {
     pub status: HttpStatus,
     pub session: TcpSession,
     pub header: HttpHeader,
+    // the urls which were redirected away from on the way to this response, earliest first
+    pub redirects: Vec<Url>,
 
     has_chunked_body: bool,
     next_chunk_bytes_available: usize,
     next_chunk_bytes_read: usize,
+    chunked_body_done: bool,
 
     body_bytes_available: usize,
     body_bytes_read: usize,
 }
 
 impl Response {
-    pub fn from_http_header(
-        mut session: TcpSession,
-        header: HttpHeader,
-    ) -> Result<Self, Box<dyn error::Error>> {
+    pub fn from_http_header(session: TcpSession, header: HttpHeader) -> Result<Self, Error> {
         let status = HttpStatus::from_string(&header.line)?;
 
         let mut body_bytes_available: usize = 0;
-        let content_length_result = header.get_value("content-length");
-        if content_length_result.is_some() {
-            let content_length_str = content_length_result.unwrap();
-            body_bytes_available = content_length_str.parse::<usize>()?;
+        if let Some(content_length_str) = header.get_value("content-length") {
+            body_bytes_available = content_length_str.parse::<usize>().map_err(|_| {
+                Error::new(
+                    ErrorKind::MalformedHeader,
+                    &format!("invalid content-length: \"{}\"", content_length_str),
+                )
+            })?;
         }
 
         let mut has_chunked_body = false;
-        let transfer_encoding_result = header.get_value("transfer-encoding");
-        let mut next_chunk_bytes_available: usize = 0;
-        if transfer_encoding_result.is_some() {
+        if let Some(encoding) = header.get_value("transfer-encoding") {
             // we only support chunked encoding
-            let encoding = transfer_encoding_result.unwrap();
             if encoding != "chunked" {
-                return Err(
-                    format!("transfer encoding of \"{}\" is not supported", encoding).into(),
-                );
+                return Err(Error::new(
+                    ErrorKind::UnsupportedTransferEncoding,
+                    &format!("transfer encoding of \"{}\" is not supported", encoding),
+                ));
             }
 
             has_chunked_body = true;
-            next_chunk_bytes_available = session.recv_chunk_header()?;
         }
 
-        Ok(Self {
+        let mut response = Self {
             body_bytes_available,
             body_bytes_read: 0,
             has_chunked_body,
-            next_chunk_bytes_available,
+            next_chunk_bytes_available: 0,
             next_chunk_bytes_read: 0,
+            chunked_body_done: false,
             status,
             session,
             header,
-        })
+            redirects: vec![],
+        };
+        if has_chunked_body {
+            response.next_chunk()?;
+        }
+        return Ok(response);
+    }
+
+    // The address the response came from, which is the proxy's when going through one
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        return self.session.peer_addr();
     }
 
     // Returns true if there is a body associated with this response which needs to be read
@@ -117,81 +150,114 @@ impl Response {
         self.body_bytes_available > 0 || self.has_chunked_body
     }
 
-    pub fn read_body(&mut self, buf: &mut [u8]) -> Result<usize, Box<dyn error::Error>> {
-        if self.has_chunked_body {
-            return self._read_body_chunked(buf);
+    pub fn read_body(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let bytes_remaining = self.segment_bytes_remaining()?;
+        if bytes_remaining == 0 || buf.is_empty() {
+            return Ok(0);
         }
 
-        if self.body_bytes_available > 0 {
-            return self._read_body_fixed(buf);
+        let smallest = bytes_remaining.min(buf.len());
+        let size = self.session.recv(&mut buf[..smallest])?;
+        if size == 0 {
+            return Err(truncated());
         }
-
-        return Ok(0);
+        self.advance(size);
+        return Ok(size);
     }
 
-    pub fn read_entire_body(&mut self, max_bytes: usize) -> Result<Vec<u8>, Box<dyn error::Error>> {
+    pub fn read_entire_body(&mut self, max_bytes: usize) -> Result<Vec<u8>, Error> {
         let mut grow_buf: Vec<u8> = vec![];
         let mut buf = [0u8; 4096];
         let mut n_bytes: usize = 1;
         while n_bytes > 0 {
             n_bytes = self.read_body(&mut buf)?;
             if n_bytes > 0 {
-                grow_buf.append(&mut grow_buf[..n_bytes].to_vec());
+                grow_buf.extend_from_slice(&buf[..n_bytes]);
             }
             if grow_buf.len() > max_bytes {
-                return Err(format!("body exceeded maximum byte limit of {}", max_bytes).into());
+                return Err(Error::new(
+                    ErrorKind::BodyTooLarge,
+                    &format!("body exceeded maximum byte limit of {}", max_bytes),
+                ));
             }
         }
 
         Ok(grow_buf)
     }
 
-    fn _read_body_fixed(&mut self, buf: &mut [u8]) -> Result<usize, Box<dyn error::Error>> {
-        // short circuit out if the body has already been consumed
-        if self.body_bytes_available <= self.body_bytes_read {
-            return Ok(0);
+    // Returns the number of body bytes which can be read before the next chunk header, moving on to the next chunk
+    // when the current one has been read in full.  0 means the body has been read in its entirety.
+    fn segment_bytes_remaining(&mut self) -> Result<usize, Error> {
+        if !self.has_chunked_body {
+            return Ok(self.body_bytes_available - self.body_bytes_read);
         }
 
-        let bytes_remaining = self.body_bytes_available - self.body_bytes_read;
-        let mut smallest = bytes_remaining;
-        if buf.len() < smallest {
-            smallest = buf.len();
+        while !self.chunked_body_done {
+            let n_bytes_left = self.next_chunk_bytes_available - self.next_chunk_bytes_read;
+            if n_bytes_left > 0 {
+                return Ok(n_bytes_left);
+            }
+            self.next_chunk()?;
         }
+        return Ok(0);
+    }
 
-        let size = self.session.recv(&mut buf[..smallest])?;
-        self.body_bytes_read += size;
-        return Ok(size);
+    fn advance(&mut self, n_bytes: usize) {
+        if self.has_chunked_body {
+            self.next_chunk_bytes_read += n_bytes;
+        } else {
+            self.body_bytes_read += n_bytes;
+        }
     }
 
-    fn _read_body_chunked(&mut self, buf: &mut [u8]) -> Result<usize, Box<dyn error::Error>> {
-        let mut n_bytes_left = self.next_chunk_bytes_available - self.next_chunk_bytes_read;
-        if n_bytes_left == 0 {
-            if self.next_chunk_bytes_available == 0 {
-                return Ok(0);
-            }
+    fn next_chunk(&mut self) -> Result<(), Error> {
+        if self.next_chunk_bytes_available > 0 {
             // Receive the final sequence in the chunk, which technically represents the delimiter
             self.session.recv_until(b"\r\n", 2)?;
+        }
 
-            let next_chunk_bytes_available = self.session.recv_chunk_header()?;
-            self.next_chunk_bytes_available = next_chunk_bytes_available;
-            self.next_chunk_bytes_read = 0;
-            if self.next_chunk_bytes_available == 0 {
-                return Ok(0);
-            }
+        self.next_chunk_bytes_available = self.session.recv_chunk_header()?;
+        self.next_chunk_bytes_read = 0;
+        if self.next_chunk_bytes_available == 0 {
+            // the last chunk is followed by optional trailer fields and then an empty line
+            while self.session.recv_until(b"\r\n", MAX_HEADER_SIZE)? != b"\r\n" {}
+            self.chunked_body_done = true;
         }
+        return Ok(());
+    }
+}
 
-        n_bytes_left = self.next_chunk_bytes_available - self.next_chunk_bytes_read;
-        if n_bytes_left == 0 {
-            return Ok(0);
+// Reads the body, so a response can be handed to anything taking a reader, e.g. io::copy
+impl Read for Response {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        return Ok(self.read_body(buf)?);
+    }
+}
+
+// Exposes the session's own buffer, limited to the body, rather than buffering it a second time
+impl BufRead for Response {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        let bytes_remaining = self.segment_bytes_remaining()?;
+        if bytes_remaining == 0 {
+            return Ok(&[]);
         }
 
-        let mut smallest = n_bytes_left;
-        if buf.len() < smallest {
-            smallest = buf.len();
+        let buf = self.session.fill_buf()?;
+        if buf.is_empty() {
+            return Err(truncated().into());
         }
+        return Ok(&buf[..bytes_remaining.min(buf.len())]);
+    }
 
-        let bytes_read = self.session.recv(&mut buf[..smallest])?;
-        self.next_chunk_bytes_read += bytes_read;
-        return Ok(bytes_read);
+    fn consume(&mut self, amt: usize) {
+        self.session.consume(amt);
+        self.advance(amt);
     }
 }
+
+fn truncated() -> Error {
+    return Error::new(
+        ErrorKind::Io,
+        "connection closed before the body was complete",
+    );
+}
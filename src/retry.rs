@@ -0,0 +1,188 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::{Error, ErrorKind},
+    request::Method,
+    response::Response,
+};
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    // total number of attempts including the first, so 1 disables retries
+    pub max_attempts: usize,
+    // the backoff before the first retry, which doubles with each subsequent retry
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+    // non-idempotent requests (POST and PATCH) are only retried when this is set
+    pub retry_non_idempotent: bool,
+    // response statuses which are retried, a Retry-After header on any of them is honored
+    pub statuses: Vec<usize>,
+    // a Retry-After longer than this is not waited out, and the response is returned as is
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+impl RetryPolicy {
+    // Retries nothing
+    pub fn none() -> Self {
+        Self::new(1)
+    }
+
+    // Retries failed connections, io errors, timeouts, 429 and 503, for idempotent requests only
+    pub fn new(max_attempts: usize) -> Self {
+        Self {
+            max_attempts,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            retry_non_idempotent: false,
+            statuses: vec![429, 503],
+            max_retry_after: Duration::from_secs(60),
+        }
+    }
+
+    // Returns how long to wait before making another attempt, or None if the outcome of attempt (counting from 1)
+    // is final
+    pub(crate) fn retry_delay(
+        &self,
+        method: Method,
+        attempt: usize,
+        result: &Result<Response, Error>,
+    ) -> Option<Duration> {
+        if attempt >= self.max_attempts || !(self.retry_non_idempotent || method.is_idempotent()) {
+            return None;
+        }
+
+        let backoff = self.backoff(attempt);
+        match result {
+            Ok(resp) => {
+                if !self.statuses.contains(&resp.status.status_code) {
+                    return None;
+                }
+
+                let Some(retry_after) = resp.header.get_value("retry-after") else {
+                    return Some(backoff);
+                };
+                // a retry-after which can't be parsed is as good as none
+                let Some(delay) = parse_retry_after(&retry_after, SystemTime::now()) else {
+                    return Some(backoff);
+                };
+                if delay > self.max_retry_after {
+                    return None;
+                }
+                return Some(delay);
+            }
+            Err(err) => match err.kind() {
+                ErrorKind::Connect | ErrorKind::Io | ErrorKind::Timeout => Some(backoff),
+                _ => None,
+            },
+        }
+    }
+
+    // Exponential backoff with jitter, somewhere between half and all of initial_backoff * 2^(attempt - 1)
+    fn backoff(&self, attempt: usize) -> Duration {
+        let exp = (attempt - 1).min(31) as u32;
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << exp)
+            .min(self.max_backoff);
+
+        let jitter = RandomState::new().build_hasher().finish() % 1000;
+        return backoff / 2 + backoff / 2 * jitter as u32 / 1000;
+    }
+}
+
+// Retry-After is either a number of seconds or an HTTP-date
+fn parse_retry_after(value: &str, now: SystemTime) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    let at = parse_http_date(value)?;
+    return Some(at.duration_since(now).unwrap_or(Duration::ZERO));
+}
+
+// Parses the preferred IMF-fixdate form of an HTTP-date, e.g. "Sun, 06 Nov 1994 08:49:37 GMT"
+fn parse_http_date(value: &str) -> Option<SystemTime> {
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() != 6 || parts[5] != "GMT" {
+        return None;
+    }
+
+    let day: u64 = parts[1].parse().ok()?;
+    let months = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let month = months.iter().position(|m| *m == parts[2])? as u64 + 1;
+    let year: u64 = parts[3].parse().ok()?;
+    let hms: Vec<u64> = parts[4]
+        .split(":")
+        .map(|p| p.parse::<u64>().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    if hms.len() != 3 || year < 1970 || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // days since the epoch for the proleptic gregorian calendar, counting years from march so that leap days fall
+    // at the end of the year
+    let (y, m) = if month <= 2 {
+        (year - 1, month + 9)
+    } else {
+        (year, month - 3)
+    };
+    let era = y / 400;
+    let yoe = y - era * 400;
+    let doy = (153 * m + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    let days = era * 146097 + doe - 719468;
+
+    let secs = days * 86400 + hms[0] * 3600 + hms[1] * 60 + hms[2];
+    return Some(UNIX_EPOCH + Duration::from_secs(secs));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert!(parse_retry_after("120", now) == Some(Duration::from_secs(120)));
+        assert!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:47 GMT", now)
+                == Some(Duration::from_secs(10))
+        );
+        assert!(parse_retry_after("Sun, 06 Nov 1994 08:49:27 GMT", now) == Some(Duration::ZERO));
+        assert!(parse_retry_after("soon", now).is_none());
+        assert!(
+            parse_http_date("Thu, 29 Feb 2024 00:00:00 GMT")
+                == Some(UNIX_EPOCH + Duration::from_secs(1709164800))
+        );
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy {
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(300),
+            ..RetryPolicy::new(5)
+        };
+
+        for _ in 0..20 {
+            let first = policy.backoff(1);
+            assert!(first >= Duration::from_millis(50) && first <= Duration::from_millis(100));
+            let second = policy.backoff(2);
+            assert!(second >= Duration::from_millis(100) && second <= Duration::from_millis(200));
+            let capped = policy.backoff(10);
+            assert!(capped >= Duration::from_millis(150) && capped <= Duration::from_millis(300));
+        }
+    }
+}
@@ -1,187 +1,632 @@
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
 use std::{
-    error::{self},
-    io::{Read, Write},
-    net::TcpStream,
-    time::Instant,
+    io::{self, Read, Write},
+    net::{SocketAddr, TcpStream},
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
     vec,
 };
 
+use crate::{
+    connector::{Connection, Connector, DEFAULT_CONNECTOR},
+    error::{Error, ErrorKind},
+    header::HttpHeader,
+    pool::{PoolKey, PoolPermit},
+    proxy::{Proxy, ProxyProtocol},
+    resolver::{Resolver, DEFAULT_RESOLVER},
+    response::HttpStatus,
+    socks,
+    timeout::Timeouts,
+    MAX_HEADER_SIZE,
+};
+
+#[cfg(feature = "tls")]
+use crate::tls;
+
+// Identifies a connector in pool keys by its address
+pub fn connector_id(connector: &Arc<dyn Connector>) -> usize {
+    return Arc::as_ptr(connector) as *const () as usize;
+}
+
+// Identifies the tls configuration securing a stream in pool keys by its address, or 0 for plain streams.  As with
+// connectors, the route of every pooled session holds on to its configuration, so the address can't be reused by
+// another while any such session remains.
+pub fn tls_config_id(security: &StreamSecurity) -> usize {
+    return match security {
+        StreamSecurity::Plain => 0,
+        #[cfg(feature = "tls")]
+        StreamSecurity::Tls { config, .. } => Arc::as_ptr(config) as usize,
+    };
+}
+
+// Describes how the stream is secured once the tcp connection has been established
+#[derive(Clone)]
+pub enum StreamSecurity {
+    Plain,
+    #[cfg(feature = "tls")]
+    Tls {
+        config: Arc<rustls::ClientConfig>,
+        server_name: String,
+    },
+}
+
+// Everything a session needs to know to open its stream, beyond the target host
+#[derive(Clone)]
+pub struct Route {
+    pub security: StreamSecurity,
+    pub proxy: Option<Proxy>,
+    pub connector: Arc<dyn Connector>,
+    // used when resolving on behalf of a socks5 proxy, the connector does its own resolution
+    pub resolver: Arc<dyn Resolver>,
+}
+
+enum Stream {
+    Plain(Box<dyn Connection>),
+    #[cfg(feature = "tls")]
+    Tls(Box<tls::TlsStream>),
+}
+
+impl Stream {
+    fn connection(&self) -> &dyn Connection {
+        match self {
+            Stream::Plain(s) => s.as_ref(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => s.sock.as_ref(),
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(s) => s.read(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Stream::Plain(s) => s.write(buf),
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Stream::Plain(s) => s.flush(),
+            #[cfg(feature = "tls")]
+            Stream::Tls(s) => s.flush(),
+        }
+    }
+}
+
 pub struct TcpSession {
     idle_from: Option<Instant>,
-    pub host: String,
-    is_fresh_conn: bool,
-    stream: Option<TcpStream>,
+    pub key: PoolKey,
+    route: Route,
+    stream: Option<Stream>,
+    // the address the stream is connected to, which is the proxy's when going through one
+    peer_addr: Option<SocketAddr>,
+    // bytes read from the stream but not yet consumed begin at buffer_pos
     buffer: Vec<u8>,
+    buffer_pos: usize,
+    timeouts: Timeouts,
+    deadline: Option<Instant>,
+    // set once an operation fails part way, after which the session is in an unknown state and must not be reused
+    broken: bool,
+    // set once anything is received in the current exchange
+    received: bool,
+    // held for as long as the session is open when it counts against a pool's limits
+    pub(crate) permit: Option<PoolPermit>,
 }
 
 impl TcpSession {
-    pub fn new(host: String) -> Self {
+    pub fn new(key: PoolKey, route: Route) -> Self {
         Self {
             idle_from: None,
-            host,
-            is_fresh_conn: true,
+            key,
+            route,
             stream: None,
+            peer_addr: None,
             buffer: vec![],
+            buffer_pos: 0,
+            timeouts: Timeouts::new(),
+            deadline: None,
+            broken: false,
+            received: false,
+            permit: None,
         }
     }
 
     pub fn from_stream(tcp_stream: TcpStream) -> Self {
+        let peer_addr = tcp_stream.peer_addr().ok();
         Self {
             idle_from: None,
-            host: tcp_stream.peer_addr().unwrap().to_string(),
-            is_fresh_conn: true,
-            stream: Some(tcp_stream),
+            key: PoolKey {
+                host: tcp_stream.peer_addr().unwrap().to_string(),
+                tls: false,
+                tls_config: 0,
+                tag: None,
+                proxy: None,
+                unix_socket: None,
+                connector: connector_id(&DEFAULT_CONNECTOR),
+            },
+            route: Route {
+                security: StreamSecurity::Plain,
+                proxy: None,
+                connector: DEFAULT_CONNECTOR.clone(),
+                resolver: DEFAULT_RESOLVER.clone(),
+            },
+            stream: Some(Stream::Plain(Box::new(tcp_stream))),
+            peer_addr,
             buffer: vec![],
+            buffer_pos: 0,
+            timeouts: Timeouts::new(),
+            deadline: None,
+            broken: false,
+            received: false,
+            permit: None,
         }
     }
 
+    // Applies the timeouts to all subsequent operations until the session is released, the total timeout is measured
+    // from now
+    pub fn set_timeouts(&mut self, timeouts: &Timeouts) {
+        self.timeouts = *timeouts;
+        self.deadline = timeouts.total.map(|total| Instant::now() + total);
+    }
+
     pub fn set_idle(&mut self) {
         self.idle_from = Some(Instant::now());
-        self.is_fresh_conn = false;
+        self.timeouts = Timeouts::new();
+        self.deadline = None;
+        self.received = false;
+    }
+
+    // True when the session was reused from the pool and nothing has been received on it since, in which case a
+    // failure most likely means the server closed the idle connection before the request reached it
+    pub fn is_stale(&self) -> bool {
+        self.idle_from.is_some() && !self.received
+    }
+
+    // Drops the stream so that the session connects afresh when next used, as a new session would
+    pub fn reconnect(&mut self) {
+        self.stream = None;
+        self.peer_addr = None;
+        self.buffer.clear();
+        self.buffer_pos = 0;
+        self.idle_from = None;
+        self.broken = false;
+    }
+
+    // Prevents the session being returned to the pool once the response has been read, for instance when the server
+    // is left expecting a request body which is never sent
+    pub fn set_broken(&mut self) {
+        self.broken = true;
+    }
+
+    pub fn is_broken(&self) -> bool {
+        self.broken
+    }
+
+    // The address the session is connected to, once it has connected over tcp
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    // When the session was last released, if it is idle
+    pub fn idle_from(&self) -> Option<Instant> {
+        self.idle_from
     }
 
     pub fn is_expired(&self, now: &Instant) -> bool {
-        if self.idle_from.is_none() {
-            return false;
+        match self.idle_from {
+            Some(idle_from) => now.duration_since(idle_from).as_secs() > 15,
+            None => false,
+        }
+    }
+
+    // Returns the time allowed for the next operation, which is the lesser of its own timeout and whatever remains
+    // until the deadline
+    fn time_allowed(&self, timeout: Option<Duration>) -> Result<Option<Duration>, Error> {
+        let Some(deadline) = self.deadline else {
+            return Ok(timeout);
+        };
+
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(Error::new(ErrorKind::Timeout, "request deadline exceeded"));
         }
 
-        return now.duration_since(self.idle_from.unwrap()).as_secs() > 15;
+        return Ok(Some(timeout.map_or(remaining, |t| t.min(remaining))));
     }
 
-    fn connect(&mut self) -> Result<(), std::io::Error> {
-        let stream = TcpStream::connect(&self.host)?;
-        self.stream = Some(stream);
-        self.is_fresh_conn = true;
-        self.buffer = vec![];
+    // Bounds the next read or write on the stream by the relevant timeout and the deadline
+    fn apply_timeouts(&mut self) -> Result<(), Error> {
+        let read_timeout = self.time_allowed(self.timeouts.read)?;
+        let write_timeout = self.time_allowed(self.timeouts.write)?;
+        if let Some(stream) = self.stream.as_ref() {
+            stream.connection().set_read_timeout(read_timeout)?;
+            stream.connection().set_write_timeout(write_timeout)?;
+        }
         Ok(())
     }
 
-    pub fn send(&mut self, buf: &[u8]) -> Result<usize, Box<dyn error::Error>> {
-        if self.stream.is_none() {
-            let _ = self.connect()?;
+    fn connect(&mut self) -> Result<(), Error> {
+        self.buffer = vec![];
+        self.buffer_pos = 0;
+        self.peer_addr = None;
+
+        if let Some(path) = self.key.unix_socket.clone() {
+            return self.connect_unix(&path);
         }
-        let n_bytes = self._send(buf)?;
-        Ok(n_bytes)
-    }
 
-    // Receives until a matching sequence of bytes is observed and a buffer up until, and including that sequence is returned, or
-    // max bytes has been read, and an error is returned.
-    pub fn recv_until(&mut self, seq: &[u8], max: usize) -> Result<Vec<u8>, Box<dyn error::Error>> {
-        if self.stream.is_none() {
-            let _ = self.connect()?;
+        let authority = match &self.route.proxy {
+            Some(proxy) => proxy.host.clone(),
+            None => self.target_authority(),
+        };
+        let connect_timeout = self.time_allowed(self.timeouts.connect)?;
+        let mut stream = self
+            .route
+            .connector
+            .connect(&authority, connect_timeout)
+            .map_err(|e| {
+                let kind = match e.kind() {
+                    io::ErrorKind::TimedOut => ErrorKind::Timeout,
+                    _ => ErrorKind::Connect,
+                };
+                Error::with_source(kind, &format!("unable to connect to {}", authority), e)
+            })?;
+        self.peer_addr = stream.peer_addr();
+
+        // bound the tunnel and the tls handshake by the read and write timeouts
+        stream.set_read_timeout(self.time_allowed(self.timeouts.read)?)?;
+        stream.set_write_timeout(self.time_allowed(self.timeouts.write)?)?;
+
+        // https always goes through a tunnel, whereas plain http is forwarded by an http proxy unless told otherwise
+        if let Some(proxy) = self.route.proxy.clone() {
+            match proxy.protocol {
+                ProxyProtocol::Socks5 | ProxyProtocol::Socks5h => {
+                    socks::connect(
+                        &mut *stream,
+                        &proxy,
+                        &self.target_authority(),
+                        self.route.resolver.as_ref(),
+                    )?;
+                }
+                ProxyProtocol::Http => {
+                    let plain = matches!(self.route.security, StreamSecurity::Plain);
+                    if !plain || !proxy.forwards_plain_http() {
+                        self.open_tunnel(&mut *stream, &proxy)?;
+                    }
+                }
+            }
         }
 
-        return self._recv_until(seq, max);
+        let stream = match &self.route.security {
+            StreamSecurity::Plain => Stream::Plain(stream),
+            #[cfg(feature = "tls")]
+            StreamSecurity::Tls {
+                config,
+                server_name,
+            } => Stream::Tls(Box::new(tls::connect(config, server_name, stream)?)),
+        };
+        self.stream = Some(stream);
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn connect_unix(&mut self, path: &Path) -> Result<(), Error> {
+        let stream = UnixStream::connect(path).map_err(|e| {
+            Error::with_source(
+                ErrorKind::Connect,
+                &format!("unable to connect to {}", path.display()),
+                e,
+            )
+        })?;
+        self.stream = Some(Stream::Plain(Box::new(stream)));
+        return self.apply_timeouts();
     }
 
-    pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize, std::io::Error> {
-        if self.stream.is_none() {
-            let _ = self.connect()?;
+    #[cfg(not(unix))]
+    fn connect_unix(&mut self, path: &Path) -> Result<(), Error> {
+        return Err(Error::new(
+            ErrorKind::Connect,
+            &format!(
+                "unable to connect to {}, unix sockets are unsupported on this platform",
+                path.display()
+            ),
+        ));
+    }
+
+    // Asks the proxy to open a tunnel to the target host, through which the session then proceeds as though it were
+    // connected to the target directly
+    fn open_tunnel(&mut self, stream: &mut dyn Connection, proxy: &Proxy) -> Result<(), Error> {
+        let target = self.target_authority();
+        let mut connect_req = format!("CONNECT {} HTTP/1.1\r\nhost: {}\r\n", target, target);
+        if let Some(authorization) = proxy.authorization() {
+            connect_req.push_str(&format!("proxy-authorization: {}\r\n", authorization));
         }
+        connect_req.push_str("\r\n");
+        stream.write_all(connect_req.as_bytes())?;
 
-        if self.buffer.len() > 0 {
-            let mut smallest: usize = buf.len();
-            if self.buffer.len() < smallest {
-                smallest = self.buffer.len();
+        let mut buf: Vec<u8> = vec![];
+        let mut t_buf = [0u8; 4096];
+        let header_end = loop {
+            if let Some(index) = buf.windows(4).position(|window| window == b"\r\n\r\n") {
+                break index + 4;
             }
-
-            for i in 0..smallest {
-                buf[i] = self.buffer[i];
+            if buf.len() > MAX_HEADER_SIZE {
+                return Err(Error::new(
+                    ErrorKind::HeaderTooLarge,
+                    "proxy response header exceeded the maximum size",
+                ));
+            }
+            let n_bytes = stream.read(&mut t_buf)?;
+            if n_bytes == 0 {
+                return Err(Error::new(
+                    ErrorKind::Proxy,
+                    &format!("proxy {} closed the connection", proxy.host),
+                ));
             }
+            buf.extend_from_slice(&t_buf[..n_bytes]);
+        };
 
-            self.buffer = self.buffer[smallest..].to_vec();
-            return Ok(smallest);
+        let header = HttpHeader::from_bytes(&buf[..header_end])?;
+        let status = HttpStatus::from_string(&header.line)?;
+        if !(200..300).contains(&status.status_code) {
+            return Err(Error::new(
+                ErrorKind::Proxy,
+                &format!(
+                    "proxy {} refused to tunnel to {}: {} {}",
+                    proxy.host, target, status.status_code, status.status_msg
+                ),
+            ));
         }
 
-        let stream = self.stream.as_mut().unwrap();
-        return stream.read(buf);
+        // nothing should follow the proxy's response until the target speaks, which over tls it only does once
+        // spoken to
+        self.buffer = buf[header_end..].to_vec();
+        if !self.buffer.is_empty() && !matches!(self.route.security, StreamSecurity::Plain) {
+            return Err(Error::new(
+                ErrorKind::Proxy,
+                "unexpected data from the proxy ahead of the tls handshake",
+            ));
+        }
+        return Ok(());
+    }
+
+    // The target's host and port, which the CONNECT request needs explicitly
+    fn target_authority(&self) -> String {
+        // the colons of a bracketed ipv6 literal don't count
+        let has_port = match self.key.host.rsplit_once("]") {
+            Some((_, rest)) => rest.starts_with(":"),
+            None => self.key.host.contains(":"),
+        };
+        if has_port {
+            return self.key.host.clone();
+        }
+
+        let port = match self.route.security {
+            StreamSecurity::Plain => 80,
+            #[cfg(feature = "tls")]
+            StreamSecurity::Tls { .. } => 443,
+        };
+        return format!("{}:{}", self.key.host, port);
+    }
+
+    pub fn send(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        let result = self._send(buf);
+        return self.check(result);
+    }
+
+    // Sends the whole of buf, however many writes it takes
+    pub fn send_all(&mut self, buf: &[u8]) -> Result<(), Error> {
+        let mut total: usize = 0;
+        while total < buf.len() {
+            total += self.send(&buf[total..])?;
+        }
+        return Ok(());
+    }
+
+    // Receives until a matching sequence of bytes is observed and a buffer up until, and including that sequence is returned, or
+    // max bytes has been read, and an error is returned.
+    pub fn recv_until(&mut self, seq: &[u8], max: usize) -> Result<Vec<u8>, Error> {
+        let result = self._recv_until(seq, max);
+        return self.check(result);
+    }
+
+    pub fn recv(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        let result = self._recv(buf);
+        return self.check(result);
+    }
+
+    // Returns the buffered bytes not yet consumed, reading more from the stream only when there are none.  An empty
+    // slice means the stream has ended.
+    pub fn fill_buf(&mut self) -> Result<&[u8], Error> {
+        let result = self._fill_buf();
+        self.check(result)?;
+        return Ok(&self.buffer[self.buffer_pos..]);
+    }
+
+    // Waits up to wait for the peer to send something, which is buffered rather than consumed.  Returns false if
+    // nothing arrived in time.
+    pub fn wait_for_data(&mut self, wait: Duration) -> Result<bool, Error> {
+        let result = self._wait_for_data(wait);
+        return self.check(result);
+    }
+
+    // Marks amt bytes returned by fill_buf as read
+    pub fn consume(&mut self, amt: usize) {
+        self.buffer_pos = (self.buffer_pos + amt).min(self.buffer.len());
     }
 
     // returns the number of bytes of the following chunk, excluding the 2 bytes representing the \r\n delimiter
-    pub fn recv_chunk_header(&mut self) -> Result<usize, Box<dyn error::Error>> {
+    pub fn recv_chunk_header(&mut self) -> Result<usize, Error> {
         let buf = self.recv_until(b"\r\n", 100)?;
-        let chunk_size_str = (String::from_utf8(buf)?).trim().to_owned();
-        let chunk_size: usize = chunk_size_str.parse()?;
+        let malformed = || Error::new(ErrorKind::MalformedHeader, "malformed chunk header");
+        let chunk_size_str = String::from_utf8(buf).map_err(|_| malformed())?;
+        // chunk extensions are permitted after a semicolon, we have no use for them
+        let size_str = match chunk_size_str.split_once(';') {
+            Some((size_str, _)) => size_str,
+            None => &chunk_size_str,
+        };
+        let chunk_size = usize::from_str_radix(size_str.trim(), 16).map_err(|_| malformed())?;
         return Ok(chunk_size);
     }
 
-    fn _recv_until(&mut self, seq: &[u8], max: usize) -> Result<Vec<u8>, Box<dyn error::Error>> {
-        let mut buf: Vec<u8> = vec![];
+    // Any failure part way through an exchange leaves the session in an unknown state, so it is marked broken and
+    // will be discarded rather than returned to the pool
+    fn check<T>(&mut self, result: Result<T, Error>) -> Result<T, Error> {
+        if result.is_err() {
+            self.broken = true;
+        }
+        return result;
+    }
+
+    fn read_stream(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.stream.is_none() {
+            self.connect()?;
+        }
+        self.apply_timeouts()?;
         let stream = self.stream.as_mut().unwrap();
+        let n_bytes = stream.read(buf)?;
+        self.received |= n_bytes > 0;
+        return Ok(n_bytes);
+    }
+
+    fn _recv(&mut self, buf: &mut [u8]) -> Result<usize, Error> {
+        if self.buffer_pos < self.buffer.len() {
+            let pending = &self.buffer[self.buffer_pos..];
+            let smallest = buf.len().min(pending.len());
+            buf[..smallest].copy_from_slice(&pending[..smallest]);
+            self.consume(smallest);
+            return Ok(smallest);
+        }
+
+        return self.read_stream(buf);
+    }
+
+    fn _fill_buf(&mut self) -> Result<(), Error> {
+        if self.buffer_pos < self.buffer.len() {
+            return Ok(());
+        }
+
+        let mut t_buf = [0u8; 8192];
+        let n_bytes = self.read_stream(&mut t_buf)?;
+        self.buffer.clear();
+        self.buffer_pos = 0;
+        self.buffer.extend_from_slice(&t_buf[..n_bytes]);
+        return Ok(());
+    }
+
+    fn _wait_for_data(&mut self, wait: Duration) -> Result<bool, Error> {
+        if self.buffer_pos < self.buffer.len() {
+            return Ok(true);
+        }
+
+        if self.stream.is_none() {
+            self.connect()?;
+        }
+        let read_timeout = self.time_allowed(self.timeouts.read)?;
+        let wait = read_timeout.map_or(wait, |t| t.min(wait));
+        // a socket can't be given a zero timeout, and there is no time to wait anyway
+        if wait.is_zero() {
+            return Ok(false);
+        }
+        let stream = self.stream.as_mut().unwrap();
+        stream.connection().set_read_timeout(Some(wait))?;
 
-        let mut total: usize = 0;
         let mut t_buf = [0u8; 4096];
-        let seq_len = seq.len();
-        let mut start = 0;
-        let mut final_index: i32 = -1;
-        while total <= max {
-            let n_bytes = stream.read(&mut t_buf)?;
-            total += n_bytes;
-            if n_bytes > 0 {
-                buf.append(&mut t_buf[..n_bytes].to_vec());
+        let n_bytes = match stream.read(&mut t_buf) {
+            Ok(n_bytes) => n_bytes,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+                ) =>
+            {
+                return Ok(false);
             }
+            Err(e) => return Err(e.into()),
+        };
+        if n_bytes == 0 {
+            return Err(Error::new(
+                ErrorKind::Io,
+                "connection closed while awaiting a response",
+            ));
+        }
+        self.received = true;
+        self.buffer.clear();
+        self.buffer_pos = 0;
+        self.buffer.extend_from_slice(&t_buf[..n_bytes]);
+        return Ok(true);
+    }
 
-            let mut o_found = false;
-            for i in start..total {
-                if i + seq_len > total {
+    fn _recv_until(&mut self, seq: &[u8], max: usize) -> Result<Vec<u8>, Error> {
+        // whatever is already buffered is searched first, only reading from the stream when the sequence isn't there
+        let mut searched: usize = 0;
+        loop {
+            let pending = &self.buffer[self.buffer_pos..];
+            let start = searched.saturating_sub(seq.len() - 1);
+            let found = pending[start..]
+                .windows(seq.len())
+                .position(|window| window == seq);
+            if let Some(index) = found {
+                let end = start + index + seq.len();
+                if end > max {
                     break;
                 }
-
-                let mut found = true;
-                for j in 0..seq_len {
-                    if buf[i + j] != seq[j] {
-                        found = false;
-                        break;
-                    }
-                }
-
-                if found {
-                    o_found = true;
-                    final_index = (i + seq_len) as i32;
-                }
+                let buf = pending[..end].to_vec();
+                self.consume(end);
+                return Ok(buf);
             }
 
-            if o_found {
+            if pending.len() > max {
                 break;
             }
+            searched = pending.len();
 
-            start = total - seq_len + 1;
+            let mut t_buf = [0u8; 4096];
+            let n_bytes = self.read_stream(&mut t_buf)?;
             if n_bytes == 0 {
-                break;
+                return Err(Error::new(
+                    ErrorKind::Io,
+                    "stream ended before sequence was found",
+                ));
             }
+            self.buffer.drain(..self.buffer_pos);
+            self.buffer_pos = 0;
+            self.buffer.extend_from_slice(&t_buf[..n_bytes]);
         }
 
-        if final_index == -1 {
-            if total <= max {
-                return Err("stream ended before sequence was found".into());
-            }
-            return Err("unable to find sequence within the supplied maximum bytes".into());
-        }
-
-        // buf now contains all the data we wanted, we need to buffer the remainder if any and then return the truncated buffer
-        let cutover_index: usize = final_index as usize;
-        if buf.len() > cutover_index {
-            self.buffer = buf[cutover_index..].to_vec();
-            buf.truncate(cutover_index);
-        }
-
-        Ok(buf)
+        return Err(Error::new(
+            ErrorKind::HeaderTooLarge,
+            "unable to find sequence within the supplied maximum bytes",
+        ));
     }
 
-    fn _send(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
-        let stream = self.stream.as_mut().unwrap();
-
-        let result = stream.write(buf);
-        if result.is_ok() {
-            return Ok(result.unwrap());
+    fn _send(&mut self, buf: &[u8]) -> Result<usize, Error> {
+        if self.stream.is_none() {
+            self.connect()?;
         }
+        self.apply_timeouts()?;
+        let stream = self.stream.as_mut().unwrap();
+        return Ok(stream.write(buf)?);
+    }
+}
 
-        if self.is_fresh_conn {
-            return Err(result.err().unwrap());
+// Hands the session's state to its permit (if any) as it goes, the pool counting broken sessions apart from others
+impl Drop for TcpSession {
+    fn drop(&mut self) {
+        if let Some(permit) = self.permit.as_mut() {
+            permit.broken = self.broken;
         }
-
-        self.connect()?;
-        let stream = self.stream.as_mut().unwrap();
-        return stream.write(buf);
     }
 }
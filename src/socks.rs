@@ -0,0 +1,175 @@
+use std::net::{IpAddr, SocketAddr};
+
+use crate::{
+    connector::Connection,
+    error::{Error, ErrorKind},
+    proxy::{Proxy, ProxyProtocol},
+    resolver::{split_authority, Resolver},
+};
+
+const VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const USERNAME_PASSWORD: u8 = 2;
+const NO_ACCEPTABLE_METHOD: u8 = 0xff;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+// Negotiates a connection to target (host:port) with the socks5 proxy at the other end of stream (RFC 1928), after
+// which the stream carries the connection to target
+pub fn connect(
+    stream: &mut dyn Connection,
+    proxy: &Proxy,
+    target: &str,
+    resolver: &dyn Resolver,
+) -> Result<(), Error> {
+    authenticate(stream, proxy)?;
+
+    let (host, port) = split_authority(target)
+        .ok_or_else(|| socks_error(&format!("invalid target: \"{}\"", target)))?;
+
+    let mut connect_req = vec![VERSION, CMD_CONNECT, 0];
+    match host.parse::<IpAddr>() {
+        Ok(ip) => push_ip(&mut connect_req, ip),
+        Err(_) if proxy.protocol == ProxyProtocol::Socks5h => {
+            if host.len() > 255 {
+                return Err(socks_error("target hostname is too long"));
+            }
+            connect_req.push(ATYP_DOMAIN);
+            connect_req.push(host.len() as u8);
+            connect_req.extend_from_slice(host.as_bytes());
+        }
+        Err(_) => {
+            let addr: SocketAddr = resolver
+                .resolve(&host, port)
+                .map_err(|e| {
+                    Error::with_source(
+                        ErrorKind::Connect,
+                        &format!("unable to resolve {}", host),
+                        e,
+                    )
+                })?
+                .into_iter()
+                .next()
+                .ok_or_else(|| {
+                    Error::new(
+                        ErrorKind::Connect,
+                        &format!("{} resolved to no addresses", host),
+                    )
+                })?;
+            push_ip(&mut connect_req, addr.ip());
+        }
+    }
+    connect_req.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&connect_req)?;
+
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply)?;
+    if reply[0] != VERSION {
+        return Err(socks_error("proxy replied with an unexpected version"));
+    }
+    if reply[1] != 0 {
+        return Err(socks_error(&format!(
+            "proxy failed to connect to {}: {}",
+            target,
+            reply_message(reply[1])
+        )));
+    }
+
+    // the address the proxy bound on our behalf is of no interest, but has to be read past
+    let addr_len = match reply[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len)?;
+            len[0] as usize
+        }
+        _ => return Err(socks_error("proxy replied with an unknown address type")),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound)?;
+    return Ok(());
+}
+
+fn authenticate(stream: &mut dyn Connection, proxy: &Proxy) -> Result<(), Error> {
+    let has_credentials = !proxy.username.is_empty() || !proxy.password.is_empty();
+    let greeting: &[u8] = if has_credentials {
+        &[VERSION, 2, NO_AUTH, USERNAME_PASSWORD]
+    } else {
+        &[VERSION, 1, NO_AUTH]
+    };
+    stream.write_all(greeting)?;
+
+    let mut choice = [0u8; 2];
+    stream.read_exact(&mut choice)?;
+    if choice[0] != VERSION {
+        return Err(socks_error("proxy replied with an unexpected version"));
+    }
+
+    match choice[1] {
+        NO_AUTH => return Ok(()),
+        USERNAME_PASSWORD if has_credentials => {}
+        NO_ACCEPTABLE_METHOD => {
+            return Err(socks_error(
+                "proxy accepted none of the offered authentication methods",
+            ));
+        }
+        _ => {
+            return Err(socks_error(
+                "proxy chose an authentication method which wasn't offered",
+            ))
+        }
+    }
+
+    // RFC 1929
+    if proxy.username.len() > 255 || proxy.password.len() > 255 {
+        return Err(socks_error(
+            "username and password are limited to 255 bytes",
+        ));
+    }
+    let mut auth_req = vec![1, proxy.username.len() as u8];
+    auth_req.extend_from_slice(proxy.username.as_bytes());
+    auth_req.push(proxy.password.len() as u8);
+    auth_req.extend_from_slice(proxy.password.as_bytes());
+    stream.write_all(&auth_req)?;
+
+    let mut status = [0u8; 2];
+    stream.read_exact(&mut status)?;
+    if status[1] != 0 {
+        return Err(socks_error("proxy rejected the username and password"));
+    }
+    return Ok(());
+}
+
+fn push_ip(buf: &mut Vec<u8>, ip: IpAddr) {
+    match ip {
+        IpAddr::V4(ip) => {
+            buf.push(ATYP_IPV4);
+            buf.extend_from_slice(&ip.octets());
+        }
+        IpAddr::V6(ip) => {
+            buf.push(ATYP_IPV6);
+            buf.extend_from_slice(&ip.octets());
+        }
+    }
+}
+
+fn reply_message(code: u8) -> &'static str {
+    match code {
+        1 => "general failure",
+        2 => "connection not allowed by ruleset",
+        3 => "network unreachable",
+        4 => "host unreachable",
+        5 => "connection refused",
+        6 => "ttl expired",
+        7 => "command not supported",
+        8 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+fn socks_error(message: &str) -> Error {
+    return Error::new(ErrorKind::Proxy, message);
+}
@@ -0,0 +1,604 @@
+use std::collections::HashMap;
+
+use crate::{
+    error::{Error, ErrorKind},
+    url::Url,
+};
+
+// The value of a template variable.  An empty list or map is undefined, as is a variable missing from the map, and
+// is left out of the expansion, whereas an empty string is not.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TemplateValue {
+    String(String),
+    List(Vec<String>),
+    // pairs are expanded in the order given
+    Map(Vec<(String, String)>),
+}
+
+impl From<&str> for TemplateValue {
+    fn from(value: &str) -> Self {
+        TemplateValue::String(value.to_owned())
+    }
+}
+
+impl From<String> for TemplateValue {
+    fn from(value: String) -> Self {
+        TemplateValue::String(value)
+    }
+}
+
+impl From<Vec<&str>> for TemplateValue {
+    fn from(values: Vec<&str>) -> Self {
+        TemplateValue::List(values.into_iter().map(str::to_owned).collect())
+    }
+}
+
+impl From<Vec<(&str, &str)>> for TemplateValue {
+    fn from(pairs: Vec<(&str, &str)>) -> Self {
+        TemplateValue::Map(
+            pairs
+                .into_iter()
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .collect(),
+        )
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Operator {
+    Simple,
+    Reserved,
+    Fragment,
+    Label,
+    Path,
+    PathParam,
+    Query,
+    Continuation,
+}
+
+// How an operator's expansion is put together (RFC 6570 appendix A)
+struct Behaviour {
+    first: &'static str,
+    sep: &'static str,
+    named: bool,
+    if_empty: &'static str,
+    allow_reserved: bool,
+}
+
+impl Operator {
+    fn from_char(c: char) -> Option<Self> {
+        return match c {
+            '+' => Some(Operator::Reserved),
+            '#' => Some(Operator::Fragment),
+            '.' => Some(Operator::Label),
+            '/' => Some(Operator::Path),
+            ';' => Some(Operator::PathParam),
+            '?' => Some(Operator::Query),
+            '&' => Some(Operator::Continuation),
+            _ => None,
+        };
+    }
+
+    fn behaviour(&self) -> Behaviour {
+        let (first, sep, named, if_empty, allow_reserved) = match self {
+            Operator::Simple => ("", ",", false, "", false),
+            Operator::Reserved => ("", ",", false, "", true),
+            Operator::Fragment => ("#", ",", false, "", true),
+            Operator::Label => (".", ".", false, "", false),
+            Operator::Path => ("/", "/", false, "", false),
+            Operator::PathParam => (";", ";", true, "", false),
+            Operator::Query => ("?", "&", true, "=", false),
+            Operator::Continuation => ("&", "&", true, "=", false),
+        };
+        return Behaviour {
+            first,
+            sep,
+            named,
+            if_empty,
+            allow_reserved,
+        };
+    }
+}
+
+#[derive(Clone, Debug)]
+struct VarSpec {
+    name: String,
+    // the number of characters of a string value to expand
+    prefix: Option<usize>,
+    explode: bool,
+}
+
+#[derive(Clone, Debug)]
+enum Part {
+    Literal(String),
+    Expression(Operator, Vec<VarSpec>),
+}
+
+// A uri template (RFC 6570), e.g. "https://api.example.com/repos/{owner}/{repo}/issues{?state,labels*}", which is
+// parsed once and may then be expanded any number of times.  All four levels are supported.
+#[derive(Clone, Debug)]
+pub struct UriTemplate {
+    parts: Vec<Part>,
+}
+
+impl UriTemplate {
+    pub fn parse(template: &str) -> Result<Self, Error> {
+        let invalid = |reason: &str| {
+            Error::new(
+                ErrorKind::InvalidUrl,
+                &format!("invalid uri template \"{}\": {}", template, reason),
+            )
+        };
+
+        let mut parts: Vec<Part> = vec![];
+        let mut rest = template;
+        while !rest.is_empty() {
+            let start = rest.find("{").unwrap_or(rest.len());
+            if rest[..start].contains("}") {
+                return Err(invalid("unexpected }"));
+            }
+            if start > 0 {
+                parts.push(Part::Literal(rest[..start].to_string()));
+            }
+            if start == rest.len() {
+                break;
+            }
+
+            let Some(end) = rest[start..].find("}") else {
+                return Err(invalid("unterminated expression"));
+            };
+            let expression = &rest[start + 1..start + end];
+            rest = &rest[start + end + 1..];
+
+            let mut chars = expression.chars();
+            let (operator, varlist) = match chars.next().and_then(Operator::from_char) {
+                Some(operator) => (operator, chars.as_str()),
+                None => (Operator::Simple, expression),
+            };
+            if varlist.starts_with(['=', ',', '!', '@', '|']) {
+                return Err(invalid("reserved operator"));
+            }
+
+            let mut vars: Vec<VarSpec> = vec![];
+            for varspec in varlist.split(",") {
+                let (name, explode, prefix) = if let Some(name) = varspec.strip_suffix("*") {
+                    (name, true, None)
+                } else if let Some((name, length)) = varspec.split_once(":") {
+                    let valid = !length.is_empty()
+                        && length.len() <= 4
+                        && !length.starts_with("0")
+                        && length.bytes().all(|b| b.is_ascii_digit());
+                    if !valid {
+                        return Err(invalid("invalid prefix modifier"));
+                    }
+                    (name, false, length.parse::<usize>().ok())
+                } else {
+                    (varspec, false, None)
+                };
+                if !is_varname(name) {
+                    return Err(invalid(&format!("invalid variable name \"{}\"", name)));
+                }
+                vars.push(VarSpec {
+                    name: name.to_string(),
+                    prefix,
+                    explode,
+                });
+            }
+            parts.push(Part::Expression(operator, vars));
+        }
+
+        return Ok(Self { parts });
+    }
+
+    // Expands the template, percent encoding the values as the operator of their expression demands
+    pub fn expand(&self, vars: &HashMap<String, TemplateValue>) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => out.push_str(&encode(literal, true)),
+                Part::Expression(operator, specs) => {
+                    expand_expression(&mut out, *operator, specs, vars)
+                }
+            }
+        }
+        return out;
+    }
+
+    // Expands the template into a url, which fails unless the expansion is an absolute url
+    pub fn expand_url(&self, vars: &HashMap<String, TemplateValue>) -> Result<Url, Error> {
+        return Url::parse(&self.expand(vars));
+    }
+}
+
+fn expand_expression(
+    out: &mut String,
+    operator: Operator,
+    specs: &[VarSpec],
+    vars: &HashMap<String, TemplateValue>,
+) {
+    let b = operator.behaviour();
+    let mut first = true;
+    for spec in specs {
+        let value = match vars.get(&spec.name) {
+            Some(TemplateValue::List(list)) if list.is_empty() => continue,
+            Some(TemplateValue::Map(map)) if map.is_empty() => continue,
+            Some(value) => value,
+            None => continue,
+        };
+
+        out.push_str(if first { b.first } else { b.sep });
+        first = false;
+
+        match value {
+            TemplateValue::String(s) => {
+                if b.named {
+                    out.push_str(&spec.name);
+                    if s.is_empty() {
+                        out.push_str(b.if_empty);
+                        continue;
+                    }
+                    out.push('=');
+                }
+                let s = match spec.prefix {
+                    Some(prefix) => s.chars().take(prefix).collect(),
+                    None => s.clone(),
+                };
+                out.push_str(&encode(&s, b.allow_reserved));
+            }
+            TemplateValue::List(list) if !spec.explode => {
+                if b.named {
+                    out.push_str(&spec.name);
+                    out.push('=');
+                }
+                let items: Vec<String> = list
+                    .iter()
+                    .map(|item| encode(item, b.allow_reserved))
+                    .collect();
+                out.push_str(&items.join(","));
+            }
+            TemplateValue::Map(map) if !spec.explode => {
+                if b.named {
+                    out.push_str(&spec.name);
+                    out.push('=');
+                }
+                let items: Vec<String> = map
+                    .iter()
+                    .flat_map(|(k, v)| [encode(k, b.allow_reserved), encode(v, b.allow_reserved)])
+                    .collect();
+                out.push_str(&items.join(","));
+            }
+            TemplateValue::List(list) => {
+                let items: Vec<String> = list
+                    .iter()
+                    .map(|item| match b.named {
+                        true if item.is_empty() => format!("{}{}", spec.name, b.if_empty),
+                        true => format!("{}={}", spec.name, encode(item, b.allow_reserved)),
+                        false => encode(item, b.allow_reserved),
+                    })
+                    .collect();
+                out.push_str(&items.join(b.sep));
+            }
+            TemplateValue::Map(map) => {
+                let items: Vec<String> = map
+                    .iter()
+                    .map(|(k, v)| match b.named && v.is_empty() {
+                        true => format!("{}{}", encode(k, b.allow_reserved), b.if_empty),
+                        false => format!(
+                            "{}={}",
+                            encode(k, b.allow_reserved),
+                            encode(v, b.allow_reserved)
+                        ),
+                    })
+                    .collect();
+                out.push_str(&items.join(b.sep));
+            }
+        }
+    }
+}
+
+// varname = varchar *( ["."] varchar ), varchar = ALPHA / DIGIT / "_" / pct-encoded
+fn is_varname(name: &str) -> bool {
+    if name.is_empty() || name.starts_with(".") || name.ends_with(".") || name.contains("..") {
+        return false;
+    }
+    let bytes = name.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = name.get(i + 1..i + 3);
+                if !hex.is_some_and(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit())) {
+                    return false;
+                }
+                i += 3;
+            }
+            b if b.is_ascii_alphanumeric() || b == b'_' || b == b'.' => i += 1,
+            _ => return false,
+        }
+    }
+    return true;
+}
+
+// Percent encodes all but the unreserved characters, or when reserved characters are allowed, all but the unreserved
+// and reserved characters and any existing escapes
+fn encode(value: &str, allow_reserved: bool) -> String {
+    let bytes = value.as_bytes();
+    let mut out = String::with_capacity(value.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        let unreserved = b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~');
+        let reserved = matches!(
+            b,
+            b':' | b'/'
+                | b'?'
+                | b'#'
+                | b'['
+                | b']'
+                | b'@'
+                | b'!'
+                | b'$'
+                | b'&'
+                | b'\''
+                | b'('
+                | b')'
+                | b'*'
+                | b'+'
+                | b','
+                | b';'
+                | b'='
+        );
+        let escape = b == b'%'
+            && value
+                .get(i + 1..i + 3)
+                .is_some_and(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()));
+
+        if unreserved || (allow_reserved && (reserved || escape)) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+        i += 1;
+    }
+    return out;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The variables of RFC 6570 section 3.2
+    fn rfc_vars() -> HashMap<String, TemplateValue> {
+        let mut vars: HashMap<String, TemplateValue> = HashMap::new();
+        let mut set = |name: &str, value: TemplateValue| {
+            vars.insert(name.to_string(), value);
+        };
+        set("count", vec!["one", "two", "three"].into());
+        set("dom", vec!["example", "com"].into());
+        set("dub", "me/too".into());
+        set("hello", "Hello World!".into());
+        set("half", "50%".into());
+        set("var", "value".into());
+        set("who", "fred".into());
+        set("base", "http://example.com/home/".into());
+        set("path", "/foo/bar".into());
+        set("list", vec!["red", "green", "blue"].into());
+        set(
+            "keys",
+            vec![("semi", ";"), ("dot", "."), ("comma", ",")].into(),
+        );
+        set("v", "6".into());
+        set("x", "1024".into());
+        set("y", "768".into());
+        set("empty", "".into());
+        set("empty_keys", TemplateValue::Map(vec![]));
+        return vars;
+    }
+
+    fn check(cases: &[(&str, &str)]) {
+        let vars = rfc_vars();
+        for (template, expected) in cases {
+            let expanded = UriTemplate::parse(template).unwrap().expand(&vars);
+            assert_eq!(&expanded, expected, "{}", template);
+        }
+    }
+
+    #[test]
+    fn test_variable_expansion() {
+        check(&[
+            ("{count}", "one,two,three"),
+            ("{count*}", "one,two,three"),
+            ("{/count}", "/one,two,three"),
+            ("{/count*}", "/one/two/three"),
+            ("{;count}", ";count=one,two,three"),
+            ("{;count*}", ";count=one;count=two;count=three"),
+            ("{?count}", "?count=one,two,three"),
+            ("{?count*}", "?count=one&count=two&count=three"),
+            ("{&count*}", "&count=one&count=two&count=three"),
+        ]);
+    }
+
+    #[test]
+    fn test_simple_expansion() {
+        check(&[
+            ("{var}", "value"),
+            ("{hello}", "Hello%20World%21"),
+            ("{half}", "50%25"),
+            ("O{empty}X", "OX"),
+            ("O{undef}X", "OX"),
+            ("{x,y}", "1024,768"),
+            ("{x,hello,y}", "1024,Hello%20World%21,768"),
+            ("?{x,empty}", "?1024,"),
+            ("?{x,undef}", "?1024"),
+            ("?{undef,y}", "?768"),
+            ("{var:3}", "val"),
+            ("{var:30}", "value"),
+            ("{list}", "red,green,blue"),
+            ("{list*}", "red,green,blue"),
+            ("{keys}", "semi,%3B,dot,.,comma,%2C"),
+            ("{keys*}", "semi=%3B,dot=.,comma=%2C"),
+        ]);
+    }
+
+    #[test]
+    fn test_reserved_expansion() {
+        check(&[
+            ("{+var}", "value"),
+            ("{+hello}", "Hello%20World!"),
+            ("{+half}", "50%25"),
+            ("{base}index", "http%3A%2F%2Fexample.com%2Fhome%2Findex"),
+            ("{+base}index", "http://example.com/home/index"),
+            ("O{+empty}X", "OX"),
+            ("O{+undef}X", "OX"),
+            ("{+path}/here", "/foo/bar/here"),
+            ("here?ref={+path}", "here?ref=/foo/bar"),
+            ("up{+path}{var}/here", "up/foo/barvalue/here"),
+            ("{+x,hello,y}", "1024,Hello%20World!,768"),
+            ("{+path,x}/here", "/foo/bar,1024/here"),
+            ("{+path:6}/here", "/foo/b/here"),
+            ("{+list}", "red,green,blue"),
+            ("{+list*}", "red,green,blue"),
+            ("{+keys}", "semi,;,dot,.,comma,,"),
+            ("{+keys*}", "semi=;,dot=.,comma=,"),
+        ]);
+    }
+
+    #[test]
+    fn test_fragment_expansion() {
+        check(&[
+            ("{#var}", "#value"),
+            ("{#hello}", "#Hello%20World!"),
+            ("{#half}", "#50%25"),
+            ("foo{#empty}", "foo#"),
+            ("foo{#undef}", "foo"),
+            ("{#x,hello,y}", "#1024,Hello%20World!,768"),
+            ("{#path,x}/here", "#/foo/bar,1024/here"),
+            ("{#path:6}/here", "#/foo/b/here"),
+            ("{#list}", "#red,green,blue"),
+            ("{#list*}", "#red,green,blue"),
+            ("{#keys}", "#semi,;,dot,.,comma,,"),
+            ("{#keys*}", "#semi=;,dot=.,comma=,"),
+        ]);
+    }
+
+    #[test]
+    fn test_label_expansion() {
+        check(&[
+            ("{.who}", ".fred"),
+            ("{.who,who}", ".fred.fred"),
+            ("{.half,who}", ".50%25.fred"),
+            ("www{.dom*}", "www.example.com"),
+            ("X{.var}", "X.value"),
+            ("X{.empty}", "X."),
+            ("X{.undef}", "X"),
+            ("X{.var:3}", "X.val"),
+            ("X{.list}", "X.red,green,blue"),
+            ("X{.list*}", "X.red.green.blue"),
+            ("X{.keys}", "X.semi,%3B,dot,.,comma,%2C"),
+            ("X{.keys*}", "X.semi=%3B.dot=..comma=%2C"),
+            ("X{.empty_keys}", "X"),
+            ("X{.empty_keys*}", "X"),
+        ]);
+    }
+
+    #[test]
+    fn test_path_expansion() {
+        check(&[
+            ("{/who}", "/fred"),
+            ("{/who,who}", "/fred/fred"),
+            ("{/half,who}", "/50%25/fred"),
+            ("{/who,dub}", "/fred/me%2Ftoo"),
+            ("{/var}", "/value"),
+            ("{/var,empty}", "/value/"),
+            ("{/var,undef}", "/value"),
+            ("{/var,x}/here", "/value/1024/here"),
+            ("{/var:1,var}", "/v/value"),
+            ("{/list}", "/red,green,blue"),
+            ("{/list*}", "/red/green/blue"),
+            ("{/list*,path:4}", "/red/green/blue/%2Ffoo"),
+            ("{/keys}", "/semi,%3B,dot,.,comma,%2C"),
+            ("{/keys*}", "/semi=%3B/dot=./comma=%2C"),
+        ]);
+    }
+
+    #[test]
+    fn test_path_param_expansion() {
+        check(&[
+            ("{;who}", ";who=fred"),
+            ("{;half}", ";half=50%25"),
+            ("{;empty}", ";empty"),
+            ("{;v,empty,who}", ";v=6;empty;who=fred"),
+            ("{;v,bar,who}", ";v=6;who=fred"),
+            ("{;x,y}", ";x=1024;y=768"),
+            ("{;x,y,empty}", ";x=1024;y=768;empty"),
+            ("{;x,y,undef}", ";x=1024;y=768"),
+            ("{;hello:5}", ";hello=Hello"),
+            ("{;list}", ";list=red,green,blue"),
+            ("{;list*}", ";list=red;list=green;list=blue"),
+            ("{;keys}", ";keys=semi,%3B,dot,.,comma,%2C"),
+            ("{;keys*}", ";semi=%3B;dot=.;comma=%2C"),
+        ]);
+    }
+
+    #[test]
+    fn test_query_expansion() {
+        check(&[
+            ("{?who}", "?who=fred"),
+            ("{?half}", "?half=50%25"),
+            ("{?x,y}", "?x=1024&y=768"),
+            ("{?x,y,empty}", "?x=1024&y=768&empty="),
+            ("{?x,y,undef}", "?x=1024&y=768"),
+            ("{?var:3}", "?var=val"),
+            ("{?list}", "?list=red,green,blue"),
+            ("{?list*}", "?list=red&list=green&list=blue"),
+            ("{?keys}", "?keys=semi,%3B,dot,.,comma,%2C"),
+            ("{?keys*}", "?semi=%3B&dot=.&comma=%2C"),
+            ("{&who}", "&who=fred"),
+            ("{&half}", "&half=50%25"),
+            ("?fixed=yes{&x}", "?fixed=yes&x=1024"),
+            ("{&x,y,empty}", "&x=1024&y=768&empty="),
+            ("{&var:3}", "&var=val"),
+            ("{&list}", "&list=red,green,blue"),
+            ("{&list*}", "&list=red&list=green&list=blue"),
+            ("{&keys}", "&keys=semi,%3B,dot,.,comma,%2C"),
+            ("{&keys*}", "&semi=%3B&dot=.&comma=%2C"),
+        ]);
+    }
+
+    #[test]
+    fn test_template_url() {
+        let template = UriTemplate::parse(
+            "https://api.example.com/repos/{owner}/{repo}/issues{?state,labels*}",
+        )
+        .unwrap();
+        let mut vars: HashMap<String, TemplateValue> = HashMap::new();
+        vars.insert("owner".to_string(), "octo cat".into());
+        vars.insert("repo".to_string(), "hello/world".into());
+        vars.insert("labels".to_string(), vec!["bug", "good first issue"].into());
+        let url = template.expand_url(&vars).unwrap();
+        assert!(url.path() == "/repos/octo%20cat/hello%2Fworld/issues");
+        assert!(url.query() == "labels=bug&labels=good%20first%20issue");
+
+        vars.insert("state".to_string(), "open".into());
+        let url = template.expand_url(&vars).unwrap();
+        assert!(url.query() == "state=open&labels=bug&labels=good%20first%20issue");
+
+        let invalid = [
+            "{var",
+            "var}",
+            "{}",
+            "{=var}",
+            "{var:0}",
+            "{var:10000}",
+            "{va r}",
+            "{.var.}",
+        ];
+        for template in invalid {
+            assert!(
+                UriTemplate::parse(template).unwrap_err().kind() == ErrorKind::InvalidUrl,
+                "{}",
+                template
+            );
+        }
+    }
+}
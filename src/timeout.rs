@@ -0,0 +1,57 @@
+use std::time::Duration;
+
+// Each timeout left as None is unbounded when set on a client, or inherits the client's value when set on a request
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub struct Timeouts {
+    // time allowed to establish the tcp connection
+    pub connect: Option<Duration>,
+    // time allowed for any single read to make progress, the connection may be idle for no longer than this
+    pub read: Option<Duration>,
+    // time allowed for any single write to make progress
+    pub write: Option<Duration>,
+    // overall deadline measured from the start of the request, covering the request, the response header and the
+    // response body
+    pub total: Option<Duration>,
+}
+
+impl Timeouts {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Returns these timeouts with any that are unset taken from fallback
+    pub fn or(&self, fallback: &Timeouts) -> Timeouts {
+        Timeouts {
+            connect: self.connect.or(fallback.connect),
+            read: self.read.or(fallback.read),
+            write: self.write.or(fallback.write),
+            total: self.total.or(fallback.total),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeouts_or() {
+        let client = Timeouts {
+            connect: Some(Duration::from_secs(1)),
+            read: Some(Duration::from_secs(2)),
+            write: None,
+            total: None,
+        };
+        let req = Timeouts {
+            read: Some(Duration::from_secs(5)),
+            total: Some(Duration::from_secs(10)),
+            ..Timeouts::new()
+        };
+
+        let merged = req.or(&client);
+        assert!(merged.connect == Some(Duration::from_secs(1)));
+        assert!(merged.read == Some(Duration::from_secs(5)));
+        assert!(merged.write.is_none());
+        assert!(merged.total == Some(Duration::from_secs(10)));
+    }
+}
@@ -0,0 +1,396 @@
+use std::sync::{Arc, LazyLock};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName},
+    ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+};
+
+use crate::{
+    connector::Connection,
+    error::{Error, ErrorKind},
+};
+
+pub type TlsStream = StreamOwned<ClientConnection, Box<dyn Connection>>;
+
+// Shared by every client which hasn't been supplied an explicit TlsConfig, so the bundled root store is only
+// assembled once per process.
+pub static DEFAULT_CLIENT_CONFIG: LazyLock<Arc<ClientConfig>> = LazyLock::new(|| {
+    return TlsConfig::new()
+        .build()
+        .expect("default tls configuration is valid");
+});
+
+pub struct TlsConfig {
+    root_certs: Option<Vec<CertificateDer<'static>>>,
+    client_cert: Option<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>)>,
+}
+
+// The private key has no Clone of its own
+impl Clone for TlsConfig {
+    fn clone(&self) -> Self {
+        Self {
+            root_certs: self.root_certs.clone(),
+            client_cert: self
+                .client_cert
+                .as_ref()
+                .map(|(certs, key)| (certs.clone(), key.clone_key())),
+        }
+    }
+}
+
+impl Default for TlsConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TlsConfig {
+    // Creates a configuration which trusts the bundled webpki root certificates and presents no client certificate
+    pub fn new() -> Self {
+        Self {
+            root_certs: None,
+            client_cert: None,
+        }
+    }
+
+    // Replaces the bundled root certificates with every certificate found in the supplied PEM bundle
+    pub fn set_root_ca_pem(&mut self, pem: &[u8]) -> Result<(), Error> {
+        let certs = CertificateDer::pem_slice_iter(pem)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| tls_error(&format!("unable to parse root ca bundle: {}", e)))?;
+        if certs.is_empty() {
+            return Err(tls_error("root ca bundle contained no certificates"));
+        }
+
+        self.root_certs = Some(certs);
+        Ok(())
+    }
+
+    // Presents the supplied certificate chain and private key when the server requests client authentication (mTLS)
+    pub fn set_client_cert_pem(&mut self, cert_chain: &[u8], key: &[u8]) -> Result<(), Error> {
+        let certs = CertificateDer::pem_slice_iter(cert_chain)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| tls_error(&format!("unable to parse client certificate: {}", e)))?;
+        if certs.is_empty() {
+            return Err(tls_error(
+                "client certificate chain contained no certificates",
+            ));
+        }
+
+        let key = PrivateKeyDer::from_pem_slice(key)
+            .map_err(|e| tls_error(&format!("unable to parse client private key: {}", e)))?;
+
+        self.client_cert = Some((certs, key));
+        Ok(())
+    }
+
+    pub(crate) fn build(&self) -> Result<Arc<ClientConfig>, Error> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .map_err(|e| tls_error(&e.to_string()))?;
+
+        let mut roots = RootCertStore::empty();
+        match &self.root_certs {
+            Some(certs) => {
+                for cert in certs {
+                    roots
+                        .add(cert.clone())
+                        .map_err(|e| tls_error(&format!("invalid root certificate: {}", e)))?;
+                }
+            }
+            None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+        }
+
+        let builder = builder.with_root_certificates(roots);
+        let config = match &self.client_cert {
+            Some((certs, key)) => builder
+                .with_client_auth_cert(certs.clone(), key.clone_key())
+                .map_err(|e| tls_error(&format!("invalid client certificate: {}", e)))?,
+            None => builder.with_no_client_auth(),
+        };
+
+        return Ok(Arc::new(config));
+    }
+}
+
+// Wraps an established tcp stream in TLS, verifying the peer certificate against server_name, which is also sent as
+// the SNI extension.  The handshake is completed before returning so certificate failures surface immediately.
+pub fn connect(
+    config: &Arc<ClientConfig>,
+    server_name: &str,
+    stream: Box<dyn Connection>,
+) -> Result<TlsStream, Error> {
+    let name = ServerName::try_from(server_name.to_owned())
+        .map_err(|_| tls_error(&format!("invalid server name: \"{}\"", server_name)))?;
+    let conn = ClientConnection::new(config.clone(), name)
+        .map_err(|e| tls_error(&format!("unable to create tls connection: {}", e)))?;
+
+    let mut tls_stream = StreamOwned::new(conn, stream);
+    while tls_stream.conn.is_handshaking() {
+        tls_stream
+            .conn
+            .complete_io(&mut tls_stream.sock)
+            .map_err(|e| Error::with_source(ErrorKind::Tls, "tls handshake failed", e))?;
+    }
+
+    Ok(tls_stream)
+}
+
+fn tls_error(message: &str) -> Error {
+    Error::new(ErrorKind::Tls, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{self, Read, Write},
+        net::{TcpListener, TcpStream},
+        thread::{self, JoinHandle},
+    };
+
+    use rustls::{server::WebPkiClientVerifier, ServerConfig, ServerConnection};
+
+    use super::*;
+    use crate::{
+        header::HttpHeader, response::HttpStatus, HttpClient, Method, Proxy, Request, SessionPool,
+        Url,
+    };
+
+    const CA_PEM: &[u8] = include_bytes!("testdata/ca.pem");
+    const SERVER_PEM: &[u8] = include_bytes!("testdata/server.pem");
+    const SERVER_KEY: &[u8] = include_bytes!("testdata/server.key");
+    const CLIENT_PEM: &[u8] = include_bytes!("testdata/client.pem");
+    const CLIENT_KEY: &[u8] = include_bytes!("testdata/client.key");
+
+    fn server_config(require_client_cert: bool) -> Arc<ServerConfig> {
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .unwrap();
+
+        let builder = if require_client_cert {
+            let mut roots = RootCertStore::empty();
+            for cert in CertificateDer::pem_slice_iter(CA_PEM) {
+                roots.add(cert.unwrap()).unwrap();
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                .build()
+                .unwrap();
+            builder.with_client_cert_verifier(verifier)
+        } else {
+            builder.with_no_client_auth()
+        };
+
+        let certs = CertificateDer::pem_slice_iter(SERVER_PEM)
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let key = PrivateKeyDer::from_pem_slice(SERVER_KEY).unwrap();
+        return Arc::new(builder.with_single_cert(certs, key).unwrap());
+    }
+
+    // Accepts a single tls connection and answers a single request with an empty 200, unless the handshake fails
+    fn serve_once(port: u16, require_client_cert: bool) -> JoinHandle<()> {
+        let listener = TcpListener::bind(format!("localhost:{}", port)).unwrap();
+        let config = server_config(require_client_cert);
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let conn = ServerConnection::new(config).unwrap();
+            let mut tls_stream = StreamOwned::new(conn, stream);
+
+            let mut recv_buf: Vec<u8> = vec![];
+            let mut buf = [0u8; 4096];
+            while !recv_buf.ends_with(b"\r\n\r\n") {
+                match tls_stream.read(&mut buf) {
+                    Ok(0) | Err(_) => return,
+                    Ok(n_bytes) => recv_buf.extend_from_slice(&buf[..n_bytes]),
+                }
+            }
+            let req_header = HttpHeader::from_bytes(&recv_buf).unwrap();
+            assert!(req_header.get_value("host").unwrap() == format!("localhost:{}", port));
+
+            let mut resp_header = HttpHeader::new();
+            resp_header.set_status_line(&HttpStatus::new(200));
+            resp_header.set_header("content-length", "0");
+            tls_stream.write_all(&resp_header.to_vec()).unwrap();
+            tls_stream.conn.send_close_notify();
+            tls_stream.flush().unwrap();
+        })
+    }
+
+    fn test_client(with_client_cert: bool) -> HttpClient {
+        let mut tls_config = TlsConfig::new();
+        tls_config.set_root_ca_pem(CA_PEM).unwrap();
+        if with_client_cert {
+            tls_config
+                .set_client_cert_pem(CLIENT_PEM, CLIENT_KEY)
+                .unwrap();
+        }
+        return HttpClient::with_tls_config(&tls_config).unwrap();
+    }
+
+    #[test]
+    fn test_https_req() {
+        let jh = serve_once(10650, false);
+
+        let client = test_client(false);
+        let req = Request::new(Method::Get, Url::new("https://localhost:10650/"));
+        let resp = client.req(&req).unwrap();
+        assert!(resp.status.status_code == 200);
+        client.release(resp).unwrap();
+        jh.join().unwrap();
+    }
+
+    #[test]
+    fn test_https_untrusted_ca() {
+        let jh = serve_once(10651, false);
+
+        // the bundled webpki roots know nothing of the test ca
+        let client = HttpClient::new();
+        let req = Request::new(Method::Get, Url::new("https://localhost:10651/"));
+        let err = client.req(&req).err().unwrap();
+        assert!(err.kind() == ErrorKind::Tls);
+        jh.join().unwrap();
+    }
+
+    #[test]
+    fn test_https_client_cert() {
+        let jh = serve_once(10652, true);
+
+        let client = test_client(false);
+        let req = Request::new(Method::Get, Url::new("https://localhost:10652/"));
+        assert!(client.req(&req).is_err());
+        jh.join().unwrap();
+
+        let jh = serve_once(10652, true);
+        let client = test_client(true);
+        let resp = client.req(&req).unwrap();
+        assert!(resp.status.status_code == 200);
+        client.release(resp).unwrap();
+        jh.join().unwrap();
+    }
+
+    #[test]
+    fn test_https_through_proxy() {
+        let jh = serve_once(10666, false);
+
+        // a proxy which opens a single tunnel and then relays bytes in both directions
+        let listener = TcpListener::bind("localhost:10667").unwrap();
+        let proxy_jh = thread::spawn(move || {
+            let (mut client_stream, _) = listener.accept().unwrap();
+            let mut recv_buf: Vec<u8> = vec![];
+            let mut buf = [0u8; 1];
+            while !recv_buf.ends_with(b"\r\n\r\n") {
+                client_stream.read_exact(&mut buf).unwrap();
+                recv_buf.push(buf[0]);
+            }
+            let req_header = HttpHeader::from_bytes(&recv_buf).unwrap();
+            assert!(req_header.line == "CONNECT localhost:10666 HTTP/1.1");
+
+            let mut server_stream = TcpStream::connect("localhost:10666").unwrap();
+            client_stream
+                .write_all(b"HTTP/1.1 200 Connection established\r\n\r\n")
+                .unwrap();
+            let mut client_read = client_stream.try_clone().unwrap();
+            let mut server_read = server_stream.try_clone().unwrap();
+            thread::spawn(move || io::copy(&mut client_read, &mut server_stream));
+            thread::spawn(move || io::copy(&mut server_read, &mut client_stream));
+        });
+
+        let mut tls_config = TlsConfig::new();
+        tls_config.set_root_ca_pem(CA_PEM).unwrap();
+        let client = HttpClient::builder()
+            .tls_config(tls_config)
+            .proxy(Proxy::new("http://localhost:10667").unwrap())
+            .build()
+            .unwrap();
+        let req = Request::new(Method::Get, Url::new("https://localhost:10666/"));
+        let resp = client.req(&req).unwrap();
+        assert!(resp.status.status_code == 200);
+        client.release(resp).unwrap();
+        proxy_jh.join().unwrap();
+        jh.join().unwrap();
+    }
+
+    // Accepts n_conns tls connections, answering each request with an empty 200 until the client hangs up, and
+    // returns the number of requests served on each connection
+    fn serve_keep_alive(port: u16, n_conns: usize) -> JoinHandle<Vec<usize>> {
+        let listener = TcpListener::bind(format!("localhost:{}", port)).unwrap();
+        let config = server_config(false);
+
+        thread::spawn(move || {
+            let mut handles = vec![];
+            for _ in 0..n_conns {
+                let (stream, _) = listener.accept().unwrap();
+                let conn = ServerConnection::new(config.clone()).unwrap();
+                handles.push(thread::spawn(move || {
+                    let mut tls_stream = StreamOwned::new(conn, stream);
+                    let mut n_reqs: usize = 0;
+                    let mut recv_buf: Vec<u8> = vec![];
+                    let mut buf = [0u8; 4096];
+                    loop {
+                        match tls_stream.read(&mut buf) {
+                            Ok(0) | Err(_) => return n_reqs,
+                            Ok(n_bytes) => recv_buf.extend_from_slice(&buf[..n_bytes]),
+                        }
+                        if recv_buf.ends_with(b"\r\n\r\n") {
+                            recv_buf.clear();
+                            let mut resp_header = HttpHeader::new();
+                            resp_header.set_status_line(&HttpStatus::new(200));
+                            resp_header.set_header("content-length", "0");
+                            tls_stream.write_all(&resp_header.to_vec()).unwrap();
+                            n_reqs += 1;
+                        }
+                    }
+                }));
+            }
+
+            let mut counts: Vec<usize> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+            counts.sort();
+            counts
+        })
+    }
+
+    #[test]
+    fn test_shared_pool_tls_configs() {
+        let jh = serve_keep_alive(10679, 2);
+
+        // clients sharing a pool only reuse sessions secured with their own tls configuration
+        let pool = SessionPool::new();
+        let mut tls_config = TlsConfig::new();
+        tls_config.set_root_ca_pem(CA_PEM).unwrap();
+        let plain_client = HttpClient::builder()
+            .pool(pool.clone())
+            .tls_config(tls_config.clone())
+            .build()
+            .unwrap();
+        tls_config
+            .set_client_cert_pem(CLIENT_PEM, CLIENT_KEY)
+            .unwrap();
+        let cert_client = HttpClient::builder()
+            .pool(pool.clone())
+            .tls_config(tls_config)
+            .build()
+            .unwrap();
+
+        let req = Request::new(Method::Get, Url::new("https://localhost:10679/"));
+        for client in [&plain_client, &cert_client, &plain_client] {
+            let resp = client.req(&req).unwrap();
+            client.release(resp).unwrap();
+        }
+
+        pool.clear();
+        assert!(jh.join().unwrap() == vec![1, 2]);
+    }
+
+    #[test]
+    fn test_invalid_root_ca() {
+        let mut tls_config = TlsConfig::new();
+        let err = tls_config
+            .set_root_ca_pem(b"not a certificate")
+            .err()
+            .unwrap();
+        assert!(err.kind() == ErrorKind::Tls);
+    }
+}
@@ -1,134 +1,957 @@
+use std::{fmt, net::Ipv6Addr, path::PathBuf};
+
+use crate::{
+    error::{Error, ErrorKind},
+    idna,
+};
+
+// The components of a url.  Other than the hostname, which holds an ipv6 literal without its brackets (and with its
+// zone id decoded), each is held as it appears in the url, percent encoded.
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct Url {
-    pub query: String,
-    pub scheme: String,
-    pub path: String,
-    pub fragment: String,
-    pub hostname: String,
-    pub port: String,
+    query: String,
+    scheme: String,
+    path: String,
+    fragment: String,
+    hostname: String,
+    port: String,
+    username: String,
+    password: String,
+}
+
+// The components of a url or relative reference as split up by RFC 3986, validated and normalized.  An absent
+// component is distinguished from an empty one, as resolving a reference depends upon it.
+struct Reference {
+    scheme: Option<String>,
+    authority: Option<Authority>,
+    path: String,
+    query: Option<String>,
+    fragment: Option<String>,
+}
+
+struct Authority {
+    username: String,
+    password: String,
+    hostname: String,
+    port: String,
 }
 
 impl Url {
+    // Parses a url leniently, never failing.  A url which is valid (see parse) and has an authority is parsed
+    // properly, anything else is split up as best it can be, e.g. "localhost:8080/path" is taken to be a host and
+    // path rather than a scheme and path.
     pub fn new(url: &str) -> Self {
-        let mut base: &str;
-        let mut query = "";
-        let mut scheme = "";
-        let mut path = String::new();
-        let mut fragment = "";
-        let hostname: &str;
-        let mut port = "";
-
-        // split off fragment
-        let result = url.split_once("#");
-        if !result.is_none() {
-            let (a, b) = result.unwrap();
-            base = a;
-            fragment = b;
-        } else {
-            base = url;
+        match Url::parse(url) {
+            Ok(parsed) if url.contains("://") => return parsed,
+            _ => return split(url),
         }
+    }
+
+    // Parses an absolute url as described by RFC 3986.  Characters which aren't permitted where they appear (such as
+    // spaces or non-ascii characters) are percent encoded, escapes are normalized and dot segments are removed from
+    // the path.
+    pub fn parse(url: &str) -> Result<Url, Error> {
+        let reference = parse_reference(url)?;
+        let Some(scheme) = reference.scheme else {
+            return Err(invalid_url(url, "it has no scheme"));
+        };
+
+        let url = Url::from_parts(
+            scheme,
+            reference.authority,
+            remove_dot_segments(&reference.path),
+            reference.query,
+            reference.fragment,
+        );
+        url.check_host()?;
+        return Ok(url);
+    }
+
+    fn from_parts(
+        scheme: String,
+        authority: Option<Authority>,
+        path: String,
+        query: Option<String>,
+        fragment: Option<String>,
+    ) -> Url {
+        let authority = authority.unwrap_or(Authority {
+            username: String::new(),
+            password: String::new(),
+            hostname: String::new(),
+            port: String::new(),
+        });
+        return Url {
+            query: query.unwrap_or_default(),
+            scheme,
+            path,
+            fragment: fragment.unwrap_or_default(),
+            hostname: authority.hostname,
+            port: authority.port,
+            username: authority.username,
+            password: authority.password,
+        };
+    }
+
+    fn empty() -> Url {
+        return Url::from_parts(String::new(), None, String::new(), None, None);
+    }
 
-        // split off query
-        let result = base.split_once("?");
-        if !result.is_none() {
-            let (a, b) = result.unwrap();
-            base = a;
-            query = b;
+    // The schemes this client speaks are meaningless without a host
+    fn check_host(&self) -> Result<(), Error> {
+        if matches!(self.scheme.as_str(), "http" | "https" | "ws" | "wss")
+            && self.hostname.is_empty()
+        {
+            return Err(invalid_url(&self.to_string(), "it has no host"));
         }
+        return Ok(());
+    }
 
-        // split off scheme
-        let result = base.split_once("://");
-        if !result.is_none() {
-            let (a, b) = result.unwrap();
-            scheme = a;
-            base = b;
+    // Starts building a url from its components, which are validated and encoded once built
+    pub fn builder() -> UrlBuilder {
+        return UrlBuilder::new();
+    }
+
+    // Sets the scheme, which must begin with a letter followed by letters, digits, +, - or .
+    pub fn set_scheme(&mut self, scheme: &str) -> Result<(), Error> {
+        if !is_scheme(scheme) {
+            return Err(invalid_url(scheme, "it isn't a valid scheme"));
         }
+        self.scheme = scheme.to_ascii_lowercase();
+        return Ok(());
+    }
 
-        // split off path
-        let result = base.split_once("/");
-        if !result.is_none() {
-            let (a, b) = result.unwrap();
-            base = a;
-            path = format!("/{}", b);
+    // Sets the host, which may be a hostname, an ipv4 address or an ipv6 address, bracketed or not and with or
+    // without a zone id.  Non-ascii characters are percent encoded.
+    pub fn set_host(&mut self, host: &str) -> Result<(), Error> {
+        let hostname =
+            if let Some(literal) = host.strip_prefix("[").and_then(|h| h.strip_suffix("]")) {
+                parse_ip_literal(host, literal)?
+            } else if host.contains(":") {
+                // an unbracketed zone id needn't be percent encoded
+                let literal = match host.split_once("%") {
+                    Some((address, zone)) if !zone.starts_with("25") => {
+                        format!("{}%25{}", address, zone)
+                    }
+                    _ => host.to_string(),
+                };
+                parse_ip_literal(host, &literal)?
+            } else {
+                parse_reg_name(host, host, self.scheme != "http+unix")?
+            };
+        if hostname.is_empty() && matches!(self.scheme.as_str(), "http" | "https" | "ws" | "wss") {
+            return Err(invalid_url(host, "the host is empty"));
         }
+        self.hostname = hostname;
+        return Ok(());
+    }
+
+    // Sets the port, None leaving the scheme's default to apply
+    pub fn set_port(&mut self, port: Option<u16>) {
+        self.port = port.map_or(String::new(), |port| port.to_string());
+    }
 
-        // split off port
-        let result = base.split_once(":");
-        if !result.is_none() {
-            let (a, b) = result.unwrap();
-            base = a;
-            port = b;
+    // Sets the path, percent encoding whatever isn't permitted in one and removing dot segments.  A relative path is
+    // made absolute should the url have a host.
+    pub fn set_path(&mut self, path: &str) -> Result<(), Error> {
+        let mut path = remove_dot_segments(&normalize(path, path, is_path_char)?);
+        if !path.is_empty() && !path.starts_with("/") && !self.hostname.is_empty() {
+            path.insert(0, '/');
         }
+        self.path = path;
+        return Ok(());
+    }
 
-        hostname = base;
+    // Sets the raw query (without the ?), percent encoding whatever isn't permitted in one
+    pub fn set_query(&mut self, query: &str) -> Result<(), Error> {
+        self.query = normalize(query, query, is_query_char)?;
+        return Ok(());
+    }
 
-        return Url {
-            query: query.to_string(),
-            scheme: scheme.to_string(),
-            path: path,
-            fragment: fragment.to_string(),
-            hostname: hostname.to_string(),
-            port: port.to_string(),
+    // Sets the fragment (without the #), percent encoding whatever isn't permitted in one
+    pub fn set_fragment(&mut self, fragment: &str) -> Result<(), Error> {
+        self.fragment = normalize(fragment, fragment, is_query_char)?;
+        return Ok(());
+    }
+
+    // The components are only changed through the setters above, so that they stay valid
+    pub fn scheme(&self) -> &str {
+        return &self.scheme;
+    }
+
+    // The hostname, an ipv6 literal being unbracketed with its zone id (if any) decoded
+    pub fn hostname(&self) -> &str {
+        return &self.hostname;
+    }
+
+    // The port as it appears in the url, empty when it has none
+    pub fn port(&self) -> &str {
+        return &self.port;
+    }
+
+    pub fn path(&self) -> &str {
+        return &self.path;
+    }
+
+    pub fn query(&self) -> &str {
+        return &self.query;
+    }
+
+    pub fn fragment(&self) -> &str {
+        return &self.fragment;
+    }
+
+    // The username and password as they appear in the url, percent encoded, see credentials for them decoded
+    pub fn username(&self) -> &str {
+        return &self.username;
+    }
+
+    pub fn password(&self) -> &str {
+        return &self.password;
+    }
+
+    // The host and port as they appear in the url, with an ipv6 literal bracketed and its zone id (if any) encoded
+    pub fn host(&self) -> String {
+        let hostname = self.bracketed_hostname(true);
+        if self.port.is_empty() {
+            return hostname;
+        }
+
+        return format!("{}:{}", hostname, self.port);
+    }
+
+    // The host and the effective port, e.g. example.com:443 for https://example.com/, which identifies the server
+    // whether or not the port is explicit
+    pub fn effective_host(&self) -> String {
+        let hostname = self.bracketed_hostname(true);
+        return match self.effective_port() {
+            Some(port) => format!("{}:{}", hostname, port),
+            None => hostname,
+        };
+    }
+
+    // The host and port as sent in the host header, which leaves out a default port, and the zone id of an ipv6
+    // literal as it only means something to this end of the connection (RFC 6874)
+    pub fn host_header(&self) -> String {
+        let hostname = self.bracketed_hostname(false);
+        return match self.effective_port() {
+            Some(port) if Some(port) != self.default_port() => format!("{}:{}", hostname, port),
+            _ => hostname,
+        };
+    }
+
+    fn bracketed_hostname(&self, with_zone: bool) -> String {
+        if !self.is_ipv6() {
+            return self.hostname.clone();
+        }
+        return match self.hostname.split_once("%") {
+            Some((address, zone)) if with_zone => format!("[{}%25{}]", address, zone),
+            Some((address, _)) => format!("[{}]", address),
+            None => format!("[{}]", self.hostname),
+        };
+    }
+
+    // The port given in the url, or else the default port of its scheme.  None if neither is known (or the port is
+    // invalid).
+    pub fn effective_port(&self) -> Option<u16> {
+        if self.port.is_empty() {
+            return self.default_port();
+        }
+        return self.port.parse::<u16>().ok();
+    }
+
+    // The well-known port of the scheme, if it has one
+    pub fn default_port(&self) -> Option<u16> {
+        return match self.scheme.as_str() {
+            "http" | "ws" => Some(80),
+            "https" | "wss" => Some(443),
+            _ => None,
         };
     }
 
-    pub fn to_string(&self) -> String {
-        let query: String;
-        let scheme: String;
-        let mut fragment = String::new();
-        let mut port = String::new();
+    // The hostname for display, with any internationalized labels converted back to unicode
+    pub fn unicode_hostname(&self) -> String {
+        return idna::to_unicode(&self.hostname);
+    }
+
+    // Whether the host is an ipv6 literal
+    pub fn is_ipv6(&self) -> bool {
+        return self.hostname.contains(":");
+    }
 
-        if self.scheme.len() > 0 {
-            scheme = format!("{}://", self.scheme);
+    // The decoded username and password, should the url carry any
+    pub fn credentials(&self) -> Option<(String, String)> {
+        if self.username.is_empty() && self.password.is_empty() {
+            return None;
+        }
+        return Some((decode_lossy(&self.username), decode_lossy(&self.password)));
+    }
+
+    // The decoded segments of the path, e.g. ["a b", "c/d"] for /a%20b/c%2Fd
+    pub fn path_segments(&self) -> Vec<String> {
+        let path = self.path.strip_prefix("/").unwrap_or(&self.path);
+        if path.is_empty() {
+            return vec![];
+        }
+        return path.split("/").map(decode_lossy).collect();
+    }
+
+    // The decoded key and value of each pair in the query, in order and including repeated keys.  A pair without an
+    // = has an empty value, and a + is taken to be a space, as in a submitted form.
+    pub fn query_pairs(&self) -> impl Iterator<Item = (String, String)> + '_ {
+        return self
+            .query
+            .split("&")
+            .filter(|pair| !pair.is_empty())
+            .map(decode_query_pair);
+    }
+
+    // Appends a pair to the query, percent encoding the key and value
+    pub fn append_query_pair(&mut self, key: &str, value: &str) {
+        if !self.query.is_empty() {
+            self.query.push('&');
+        }
+        self.query.push_str(&format!(
+            "{}={}",
+            percent_encode(key, is_unreserved),
+            percent_encode(value, is_unreserved)
+        ));
+    }
+
+    // Sets the value of key, replacing the first pair with that key and removing any others, or else appending a pair
+    pub fn set_query_pair(&mut self, key: &str, value: &str) {
+        let pair = format!(
+            "{}={}",
+            percent_encode(key, is_unreserved),
+            percent_encode(value, is_unreserved)
+        );
+        let mut replaced = false;
+        let mut pairs: Vec<&str> = vec![];
+        for existing in self.query.split("&").filter(|p| !p.is_empty()) {
+            if decode_query_pair(existing).0 != key {
+                pairs.push(existing);
+            } else if !replaced {
+                pairs.push(&pair);
+                replaced = true;
+            }
+        }
+        if !replaced {
+            pairs.push(&pair);
+        }
+        self.query = pairs.join("&");
+    }
+
+    // Removes every pair with key from the query
+    pub fn remove_query_key(&mut self, key: &str) {
+        self.query = self
+            .query
+            .split("&")
+            .filter(|pair| !pair.is_empty() && decode_query_pair(pair).0 != key)
+            .collect::<Vec<&str>>()
+            .join("&");
+    }
+
+    pub fn clear_query(&mut self) {
+        self.query.clear();
+    }
+
+    pub fn resource(&self) -> String {
+        let path = if self.path.is_empty() {
+            "/".to_string()
         } else {
-            scheme = self.scheme.clone();
+            self.path.clone()
+        };
+
+        if self.query.is_empty() {
+            return path;
         }
 
-        if self.port.len() > 0 {
-            port = format!(":{}", self.port);
+        return format!("{}?{}", path, self.query);
+    }
+
+    // The unix socket an http+unix url refers to, whose percent encoded path takes the place of the host, e.g.
+    // http+unix://%2Fvar%2Frun%2Fdocker.sock/v1.43/containers/json
+    pub fn unix_socket(&self) -> Option<PathBuf> {
+        if self.scheme != "http+unix" {
+            return None;
         }
+        return percent_decode(&self.hostname).map(PathBuf::from);
+    }
 
-        if self.query.len() > 0 {
-            query = format!("?{}", self.query);
+    // Resolves a reference, such as the value of a location header, against this url (RFC 3986 section 5.2)
+    pub fn join(&self, reference: &str) -> Result<Url, Error> {
+        let r = parse_reference(reference)?;
+        let base_authority = || Authority {
+            username: self.username.clone(),
+            password: self.password.clone(),
+            hostname: self.hostname.clone(),
+            port: self.port.clone(),
+        };
+
+        let (authority, path, query) = if r.scheme.is_some() || r.authority.is_some() {
+            (r.authority, remove_dot_segments(&r.path), r.query)
+        } else if r.path.is_empty() {
+            let query = r.query.or_else(|| Some(self.query.clone()));
+            (Some(base_authority()), self.path.clone(), query)
+        } else if r.path.starts_with("/") {
+            (
+                Some(base_authority()),
+                remove_dot_segments(&r.path),
+                r.query,
+            )
         } else {
-            query = self.query.clone();
+            (
+                Some(base_authority()),
+                remove_dot_segments(&self.merge(&r.path)),
+                r.query,
+            )
+        };
+
+        let scheme = r.scheme.unwrap_or_else(|| self.scheme.clone());
+        let url = Url::from_parts(scheme, authority, path, query, r.fragment);
+        url.check_host()?;
+        return Ok(url);
+    }
+
+    // Appends a relative path to all but the last segment of this url's path
+    fn merge(&self, path: &str) -> String {
+        if self.has_authority() && self.path.is_empty() {
+            return format!("/{}", path);
         }
+        return match self.path.rfind("/") {
+            Some(index) => format!("{}{}", &self.path[..index + 1], path),
+            None => path.to_string(),
+        };
+    }
 
-        if self.fragment.len() > 0 {
-            fragment = format!("#{}", self.fragment);
+    fn has_authority(&self) -> bool {
+        return !self.hostname.is_empty()
+            || !self.port.is_empty()
+            || !self.username.is_empty()
+            || !self.password.is_empty()
+            || self.path.starts_with("/");
+    }
+}
+
+// Builds a url from its components, e.g.
+// Url::builder().scheme("https").host("example.com").path_segment("a b").query_pair("q", "x&y").build()
+#[derive(Clone, Debug)]
+pub struct UrlBuilder {
+    scheme: String,
+    host: String,
+    port: Option<u16>,
+    // percent encoded, as are the query and fragment
+    path: String,
+    query: String,
+    fragment: String,
+    // the first segment which was . or .., which build rejects
+    dot_segment: Option<String>,
+}
+
+impl Default for UrlBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl UrlBuilder {
+    // Starts with an http url with neither host nor path
+    pub fn new() -> Self {
+        Self {
+            scheme: "http".to_string(),
+            host: String::new(),
+            port: None,
+            path: String::new(),
+            query: String::new(),
+            fragment: String::new(),
+            dot_segment: None,
         }
-        return format!(
-            "{}{}{}{}{}{}",
-            scheme, self.hostname, port, self.path, query, fragment
-        );
     }
 
-    pub fn host(&self) -> String {
-        let mut port = String::new();
+    pub fn scheme(mut self, scheme: &str) -> Self {
+        self.scheme = scheme.to_owned();
+        self
+    }
+
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = host.to_owned();
+        self
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = Some(port);
+        self
+    }
 
-        if self.port.len() > 0 {
-            port = format!(":{}", self.port);
+    // Sets the whole path, which is encoded as Url::set_path does, replacing any segments added so far
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_owned();
+        self
+    }
+
+    // Appends a segment to the path, percent encoding it in full so that a / becomes part of the segment.  A segment
+    // of . or .. fails the build, rather than being resolved against the segments before it.
+    pub fn path_segment(mut self, segment: &str) -> Self {
+        if matches!(segment, "." | "..") && self.dot_segment.is_none() {
+            self.dot_segment = Some(segment.to_string());
         }
+        self.path.push('/');
+        self.path
+            .push_str(&percent_encode(segment, is_segment_char));
+        self
+    }
 
-        return format!("{}{}", self.hostname, port);
+    // Appends a pair to the query, as Url::append_query_pair does
+    pub fn query_pair(mut self, key: &str, value: &str) -> Self {
+        let mut url = Url::empty();
+        url.query = self.query;
+        url.append_query_pair(key, value);
+        self.query = url.query;
+        self
     }
 
-    pub fn resource(&self) -> String {
-        let path: String;
-        let query: String;
+    pub fn fragment(mut self, fragment: &str) -> Self {
+        self.fragment = fragment.to_owned();
+        self
+    }
+
+    pub fn build(self) -> Result<Url, Error> {
+        if let Some(segment) = &self.dot_segment {
+            return Err(invalid_url(segment, "a path segment can't be . or .."));
+        }
+        let mut url = Url::empty();
+        url.set_scheme(&self.scheme)?;
+        url.set_host(&self.host)?;
+        url.set_port(self.port);
+        url.set_path(&self.path)?;
+        url.query = self.query;
+        url.set_fragment(&self.fragment)?;
+        return Ok(url);
+    }
+}
 
-        if self.path.len() > 0 {
-            path = format!("{}", self.query);
+// The lenient parse used by Url::new for anything parse rejects, which splits the url at its delimiters without
+// validating or normalizing any of it
+fn split(url: &str) -> Url {
+    let mut base: &str;
+    let mut query = "";
+    let mut scheme = "";
+    let mut path = String::new();
+    let mut fragment = "";
+    let mut port = "";
+
+    // split off fragment
+    if let Some((a, b)) = url.split_once("#") {
+        base = a;
+        fragment = b;
+    } else {
+        base = url;
+    }
+
+    // split off query
+    if let Some((a, b)) = base.split_once("?") {
+        base = a;
+        query = b;
+    }
+
+    // split off scheme
+    if let Some((a, b)) = base.split_once("://") {
+        scheme = a;
+        base = b;
+    }
+
+    // split off path
+    if let Some((a, b)) = base.split_once("/") {
+        base = a;
+        path = format!("/{}", b);
+    }
+
+    // split off port, an ipv6 literal being bracketed as it contains colons of its own.  Any zone id is kept decoded in
+    // the hostname, e.g. [fe80::1%25eth0] becomes fe80::1%eth0.
+    let hostname = match base.strip_prefix("[").and_then(|b| b.split_once("]")) {
+        Some((literal, rest)) => {
+            port = rest.strip_prefix(":").unwrap_or("");
+            literal.replacen("%25", "%", 1)
+        }
+        None => {
+            if let Some((a, b)) = base.split_once(":") {
+                base = a;
+                port = b;
+            }
+            base.to_string()
+        }
+    };
+
+    return Url {
+        query: query.to_string(),
+        scheme: scheme.to_string(),
+        path,
+        fragment: fragment.to_string(),
+        hostname,
+        port: port.to_string(),
+        username: String::new(),
+        password: String::new(),
+    };
+}
+
+// Splits a url or relative reference into its components (RFC 3986 appendix B), each of which is then validated
+fn parse_reference(input: &str) -> Result<Reference, Error> {
+    let (rest, fragment) = match input.split_once("#") {
+        Some((rest, fragment)) => (rest, Some(fragment)),
+        None => (input, None),
+    };
+    let (rest, query) = match rest.split_once("?") {
+        Some((rest, query)) => (rest, Some(query)),
+        None => (rest, None),
+    };
+
+    // a colon ahead of any slash ends the scheme, otherwise it belongs to the path
+    let (scheme, rest) = match rest.split_once(":") {
+        Some((scheme, rest)) if !scheme.contains("/") => (Some(scheme), rest),
+        _ => (None, rest),
+    };
+    let (authority, path) = match rest.strip_prefix("//") {
+        Some(rest) => match rest.find("/") {
+            Some(index) => (Some(&rest[..index]), &rest[index..]),
+            None => (Some(rest), ""),
+        },
+        None => (None, rest),
+    };
+
+    let scheme = match scheme {
+        Some(scheme) => {
+            if !is_scheme(scheme) {
+                return Err(invalid_url(input, "its scheme is invalid"));
+            }
+            Some(scheme.to_ascii_lowercase())
+        }
+        None => None,
+    };
+    let authority = authority
+        .map(|authority| parse_authority(input, authority, scheme.as_deref() != Some("http+unix")))
+        .transpose()?;
+
+    return Ok(Reference {
+        scheme,
+        authority,
+        path: normalize(input, path, is_path_char)?,
+        query: query
+            .map(|q| normalize(input, q, is_query_char))
+            .transpose()?,
+        fragment: fragment
+            .map(|f| normalize(input, f, is_query_char))
+            .transpose()?,
+    });
+}
+
+fn parse_authority(input: &str, authority: &str, idna: bool) -> Result<Authority, Error> {
+    // an unencoded @ in a password is common enough that the last one is taken to end the userinfo
+    let (userinfo, host) = match authority.rsplit_once("@") {
+        Some((userinfo, host)) => (Some(userinfo), host),
+        None => (None, authority),
+    };
+    let (username, password) = match userinfo {
+        Some(userinfo) => {
+            let (username, password) = userinfo.split_once(":").unwrap_or((userinfo, ""));
+            (
+                normalize(input, username, is_userinfo_char)?,
+                normalize(input, password, is_userinfo_char)?,
+            )
+        }
+        None => (String::new(), String::new()),
+    };
+
+    let (hostname, port) = if let Some(literal) = host.strip_prefix("[") {
+        let Some((literal, rest)) = literal.split_once("]") else {
+            return Err(invalid_url(input, "its ip literal is unterminated"));
+        };
+        let port = match rest {
+            "" => "",
+            _ => rest
+                .strip_prefix(":")
+                .ok_or_else(|| invalid_url(input, "its ip literal is followed by garbage"))?,
+        };
+        (parse_ip_literal(input, literal)?, port)
+    } else {
+        let (hostname, port) = host.split_once(":").unwrap_or((host, ""));
+        (parse_reg_name(input, hostname, idna)?, port)
+    };
+
+    if !port.bytes().all(|b| b.is_ascii_digit())
+        || (!port.is_empty() && port.parse::<u16>().is_err())
+    {
+        return Err(invalid_url(input, "its port is invalid"));
+    }
+
+    return Ok(Authority {
+        username,
+        password,
+        hostname,
+        port: port.to_string(),
+    });
+}
+
+// Validates a hostname or ipv4 address.  A hostname with non-ascii characters (whether or not percent encoded) is
+// converted to its ascii form when idna is set, and percent encoded otherwise.
+fn parse_reg_name(input: &str, hostname: &str, idna: bool) -> Result<String, Error> {
+    if hostname
+        .bytes()
+        .any(|b| b < 0x80 && !is_host_char(b) && b != b'%')
+    {
+        return Err(invalid_url(input, "its host contains an invalid character"));
+    }
+    let normalized = normalize(input, hostname, is_host_char)?;
+    let decoded = match percent_decode(&normalized) {
+        Some(decoded) if idna && !decoded.is_ascii() => decoded,
+        _ => return Ok(normalized),
+    };
+
+    let invalid = || {
+        invalid_url(
+            input,
+            "its host isn't a valid internationalized domain name",
+        )
+    };
+    if decoded
+        .chars()
+        .any(|c| c.is_ascii() && !(c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_')))
+    {
+        return Err(invalid());
+    }
+    return idna::to_ascii(&decoded).ok_or_else(invalid);
+}
+
+// Validates the contents of the brackets around an ipv6 (or future) address, decoding any zone id (RFC 6874)
+fn parse_ip_literal(input: &str, literal: &str) -> Result<String, Error> {
+    // IPvFuture, "v" 1*HEXDIG "." 1*( unreserved / sub-delims / ":" )
+    if let Some(future) = literal.strip_prefix(['v', 'V']) {
+        let valid = future.split_once(".").is_some_and(|(version, address)| {
+            !version.is_empty()
+                && version.bytes().all(|b| b.is_ascii_hexdigit())
+                && !address.is_empty()
+                && address.bytes().all(|b| is_host_char(b) || b == b':')
+        });
+        if !valid {
+            return Err(invalid_url(input, "its ip literal is invalid"));
+        }
+        return Ok(literal.to_string());
+    }
+
+    let (address, zone) = match literal.split_once("%25") {
+        Some((address, zone)) => (address, Some(zone)),
+        None => (literal, None),
+    };
+    if address.parse::<Ipv6Addr>().is_err() {
+        return Err(invalid_url(input, "its ipv6 address is invalid"));
+    }
+    let Some(zone) = zone else {
+        return Ok(address.to_string());
+    };
+
+    let zone = percent_decode(zone)
+        .filter(|zone| !zone.is_empty() && zone.bytes().all(is_unreserved))
+        .ok_or_else(|| invalid_url(input, "its ipv6 zone id is invalid"))?;
+    return Ok(format!("{}%{}", address, zone));
+}
+
+// Percent encodes any byte which isn't allowed (nor part of an escape), and normalizes escapes by decoding those of
+// unreserved characters and upper casing the rest (RFC 3986 section 6.2.2)
+fn normalize(input: &str, component: &str, allowed: fn(u8) -> bool) -> Result<String, Error> {
+    let bytes = component.as_bytes();
+    let mut out = String::with_capacity(component.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        if b == b'%' {
+            let decoded = component
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                .ok_or_else(|| invalid_url(input, "it contains an invalid percent escape"))?;
+            push_byte(&mut out, decoded, is_unreserved);
+            i += 3;
         } else {
-            path = "/".to_string();
+            push_byte(&mut out, b, allowed);
+            i += 1;
         }
+    }
+    return Ok(out);
+}
+
+fn push_byte(out: &mut String, b: u8, allowed: fn(u8) -> bool) {
+    if b < 0x80 && allowed(b) {
+        out.push(b as char);
+    } else {
+        out.push_str(&format!("%{:02X}", b));
+    }
+}
+
+// Percent encodes every byte which isn't allowed, including any %
+fn percent_encode(input: &str, allowed: fn(u8) -> bool) -> String {
+    let mut out = String::with_capacity(input.len());
+    for b in input.bytes() {
+        push_byte(&mut out, b, allowed);
+    }
+    return out;
+}
 
-        if self.query.len() > 0 {
-            query = format!("?{}", self.query);
+fn decode_query_pair(pair: &str) -> (String, String) {
+    let (key, value) = pair.split_once("=").unwrap_or((pair, ""));
+    let decode = |s: &str| decode_lossy(&s.replace("+", " "));
+    return (decode(key), decode(value));
+}
+
+// Decodes %XX escapes, returning None should an escape be invalid or the result not be utf-8
+pub(crate) fn percent_decode(s: &str) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out: Vec<u8> = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = s.get(i + 1..i + 3)?;
+            out.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
         } else {
-            query = self.query.clone();
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    return String::from_utf8(out).ok();
+}
+
+// Decodes the escapes of a component which has already been validated, replacing any invalid utf-8
+fn decode_lossy(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out: Vec<u8> = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        match s.get(i + 1..i + 3).map(|hex| u8::from_str_radix(hex, 16)) {
+            Some(Ok(b)) if bytes[i] == b'%' => {
+                out.push(b);
+                i += 3;
+            }
+            _ => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    return String::from_utf8_lossy(&out).into_owned();
+}
+
+fn is_scheme(scheme: &str) -> bool {
+    return scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+        && scheme
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '+' || c == '-' || c == '.');
+}
+
+fn is_unreserved(b: u8) -> bool {
+    return b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~');
+}
+
+fn is_sub_delim(b: u8) -> bool {
+    return matches!(
+        b,
+        b'!' | b'$' | b'&' | b'\'' | b'(' | b')' | b'*' | b'+' | b',' | b';' | b'='
+    );
+}
+
+fn is_host_char(b: u8) -> bool {
+    return is_unreserved(b) || is_sub_delim(b);
+}
+
+fn is_userinfo_char(b: u8) -> bool {
+    return is_unreserved(b) || is_sub_delim(b) || b == b':';
+}
+
+fn is_path_char(b: u8) -> bool {
+    return is_unreserved(b) || is_sub_delim(b) || matches!(b, b':' | b'@' | b'/');
+}
+
+fn is_segment_char(b: u8) -> bool {
+    return is_unreserved(b) || is_sub_delim(b) || matches!(b, b':' | b'@');
+}
+
+fn is_query_char(b: u8) -> bool {
+    return is_path_char(b) || b == b'?';
+}
+
+fn invalid_url(url: &str, reason: &str) -> Error {
+    return Error::new(
+        ErrorKind::InvalidUrl,
+        &format!("invalid url \"{}\": {}", url, reason),
+    );
+}
+
+// Resolves the . and .. segments of a path (RFC 3986 section 5.2.4)
+fn remove_dot_segments(path: &str) -> String {
+    let mut input = path;
+    let mut output = String::with_capacity(path.len());
+    let pop_segment = |output: &mut String| match output.rfind("/") {
+        Some(index) => output.truncate(index),
+        None => output.clear(),
+    };
+
+    while !input.is_empty() {
+        if let Some(rest) = input
+            .strip_prefix("../")
+            .or_else(|| input.strip_prefix("./"))
+        {
+            input = rest;
+        } else if input.starts_with("/./") {
+            input = &input[2..];
+        } else if input == "/." {
+            input = "/";
+        } else if input.starts_with("/../") {
+            input = &input[3..];
+            pop_segment(&mut output);
+        } else if input == "/.." {
+            input = "/";
+            pop_segment(&mut output);
+        } else if input == "." || input == ".." {
+            input = "";
+        } else {
+            // move the first segment, along with any slash ahead of it, to the output
+            let start = if input.starts_with("/") { 1 } else { 0 };
+            let end = input[start..]
+                .find("/")
+                .map_or(input.len(), |index| start + index);
+            output.push_str(&input[..end]);
+            input = &input[end..];
+        }
+    }
+
+    return output;
+}
+
+impl fmt::Display for Url {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.scheme.is_empty() {
+            write!(f, "{}:", self.scheme)?;
+            if self.has_authority() {
+                write!(f, "//")?;
+            }
+        }
+
+        if !self.username.is_empty() || !self.password.is_empty() {
+            write!(f, "{}", self.username)?;
+            if !self.password.is_empty() {
+                write!(f, ":{}", self.password)?;
+            }
+            write!(f, "@")?;
+        }
+
+        write!(f, "{}{}", self.host(), self.path)?;
+
+        if !self.query.is_empty() {
+            write!(f, "?{}", self.query)?;
+        }
+
+        if !self.fragment.is_empty() {
+            write!(f, "#{}", self.fragment)?;
         }
 
-        return format!("{}{}", path, query);
+        Ok(())
     }
 }
 
@@ -146,4 +969,346 @@ mod tests {
         assert!(u.query == "a=1&b=2");
         assert!(u.fragment == "test")
     }
+
+    #[test]
+    fn test_url_parse() {
+        let u = Url::parse("HTTP://us%65r:p@ss@Example.com:8080/a/./b/../c%7e%2f/d e?q=a b&r=1")
+            .unwrap();
+        assert!(u.scheme == "http");
+        assert!(u.username == "user");
+        assert!(u.password == "p%40ss");
+        assert!(u.hostname == "Example.com");
+        assert!(u.port == "8080");
+        assert!(u.path == "/a/c~%2F/d%20e");
+        assert!(u.query == "q=a%20b&r=1");
+        assert!(u.path_segments() == ["a", "c~/", "d e"]);
+        assert!(u.credentials() == Some(("user".to_string(), "p@ss".to_string())));
+        assert!(u.resource() == "/a/c~%2F/d%20e?q=a%20b&r=1");
+
+        let u = Url::parse("http://example.com/caf\u{e9}#frag#ment").unwrap();
+        assert!(u.path == "/caf%C3%A9");
+        assert!(u.fragment == "frag%23ment");
+        assert!(u.to_string() == "http://example.com/caf%C3%A9#frag%23ment");
+
+        let u = Url::parse("mailto:someone@example.com").unwrap();
+        assert!(u.hostname.is_empty());
+        assert!(u.path == "someone@example.com");
+        assert!(u.to_string() == "mailto:someone@example.com");
+        assert!(Url::parse("file:///etc/hosts").unwrap().to_string() == "file:///etc/hosts");
+
+        let invalid = [
+            "example.com/path",
+            "/path",
+            "1http://example.com/",
+            "http://exa mple.com/",
+            "http://example.com:port/",
+            "http://example.com:65536/",
+            "http://example.com/%zz",
+            "http://[::1/",
+            "http://[::g]/",
+            "http://[::1]x/",
+            "http:///path",
+            "https:path",
+        ];
+        for url in invalid {
+            assert!(
+                Url::parse(url).unwrap_err().kind() == ErrorKind::InvalidUrl,
+                "{}",
+                url
+            );
+        }
+
+        // new remains lenient, and takes a scheme-less host and port to be just that
+        let u = Url::new("localhost:8080/x");
+        assert!(u.hostname == "localhost");
+        assert!(u.port == "8080");
+        assert!(Url::new("http://example.com/%zz").path == "/%zz");
+    }
+
+    #[test]
+    fn test_ipv6_literal() {
+        let u = Url::new("http://[::1]:8080/x?a=1");
+        assert!(u.hostname == "::1");
+        assert!(u.port == "8080");
+        assert!(u.path == "/x");
+        assert!(u.host() == "[::1]:8080");
+        assert!(u.to_string() == "http://[::1]:8080/x?a=1");
+
+        let u = Url::new("https://[2001:db8::7]/");
+        assert!(u.hostname == "2001:db8::7");
+        assert!(u.port.is_empty());
+        assert!(u.host() == "[2001:db8::7]");
+
+        let u = Url::new("http://[fe80::1%25eth0]:80/");
+        assert!(u.hostname == "fe80::1%eth0");
+        assert!(u.host() == "[fe80::1%25eth0]:80");
+        assert!(u.host_header() == "[fe80::1]");
+        assert!(u.to_string() == "http://[fe80::1%25eth0]:80/");
+
+        assert!(!Url::new("http://example.com:80/").is_ipv6());
+        assert!(Url::new("http://example.com:8080/").host_header() == "example.com:8080");
+        assert!(Url::parse("http://[v1.fe:80]/").unwrap().host() == "[v1.fe:80]");
+    }
+
+    #[test]
+    fn test_default_port() {
+        let u = Url::parse("http://example.com/").unwrap();
+        assert!(u.effective_port() == Some(80));
+        assert!(u.effective_host() == "example.com:80");
+        assert!(u.host_header() == "example.com");
+
+        let u = Url::parse("https://example.com:443/").unwrap();
+        assert!(u.effective_port() == Some(443));
+        assert!(u.effective_host() == "example.com:443");
+        assert!(u.host_header() == "example.com");
+        assert!(u.host() == "example.com:443");
+
+        let u = Url::parse("https://example.com:8443/").unwrap();
+        assert!(u.effective_port() == Some(8443));
+        assert!(u.host_header() == "example.com:8443");
+
+        assert!(Url::parse("ws://example.com/").unwrap().effective_port() == Some(80));
+        assert!(Url::parse("wss://example.com/").unwrap().effective_port() == Some(443));
+        assert!(Url::parse("wss://[::1]:443/").unwrap().host_header() == "[::1]");
+        assert!(
+            Url::parse("wss://[fe80::1%25lo]/")
+                .unwrap()
+                .effective_host()
+                == "[fe80::1%25lo]:443"
+        );
+
+        let u = Url::parse("ftp://example.com/").unwrap();
+        assert!(u.effective_port().is_none());
+        assert!(u.effective_host() == "example.com");
+    }
+
+    #[test]
+    fn test_query_pairs() {
+        let mut u = Url::parse("http://example.com/search?q=a+b&tag=x&tag=y%26z&flag&=v").unwrap();
+        let pairs: Vec<(String, String)> = u.query_pairs().collect();
+        let expected = [
+            ("q", "a b"),
+            ("tag", "x"),
+            ("tag", "y&z"),
+            ("flag", ""),
+            ("", "v"),
+        ];
+        assert!(pairs.len() == expected.len());
+        for ((key, value), (expected_key, expected_value)) in pairs.iter().zip(expected) {
+            assert!(key == expected_key && value == expected_value);
+        }
+
+        u.remove_query_key("");
+        u.remove_query_key("flag");
+        u.append_query_pair("next", "/a?b=c&d e+f");
+        assert!(u.query == "q=a+b&tag=x&tag=y%26z&next=%2Fa%3Fb%3Dc%26d%20e%2Bf");
+        assert!(u.query_pairs().last() == Some(("next".to_string(), "/a?b=c&d e+f".to_string())));
+
+        // the first tag is replaced in place, and the rest removed
+        u.set_query_pair("tag", "caf\u{e9}");
+        assert!(u.query == "q=a+b&tag=caf%C3%A9&next=%2Fa%3Fb%3Dc%26d%20e%2Bf");
+        u.set_query_pair("page", "2");
+        assert!(u.query.ends_with("&page=2"));
+        u.remove_query_key("tag");
+        u.remove_query_key("next");
+        assert!(u.to_string() == "http://example.com/search?q=a+b&page=2");
+
+        u.clear_query();
+        assert!(u.resource() == "/search");
+        assert!(u.query_pairs().next().is_none());
+        u.set_query_pair("a b", "=");
+        assert!(u.resource() == "/search?a%20b=%3D");
+    }
+
+    #[test]
+    fn test_url_builder() {
+        let u = Url::builder()
+            .scheme("HTTPS")
+            .host("example.com")
+            .port(8443)
+            .path_segment("repos")
+            .path_segment("a b/c")
+            .query_pair("state", "open & closed")
+            .fragment("top")
+            .build()
+            .unwrap();
+        assert!(
+            u.to_string()
+                == "https://example.com:8443/repos/a%20b%2Fc?state=open%20%26%20closed#top"
+        );
+        assert!(u.path_segments() == ["repos", "a b/c"]);
+
+        let u = Url::builder().host("::1").path("x/../y z").build().unwrap();
+        assert!(u.to_string() == "http://[::1]/y%20z");
+        let u = Url::builder().host("fe80::1%eth0").build().unwrap();
+        assert!(u.host() == "[fe80::1%25eth0]");
+
+        assert!(Url::builder().build().is_err());
+        assert!(Url::builder().scheme("1http").host("a").build().is_err());
+        assert!(Url::builder().host("a b").build().is_err());
+        assert!(Url::builder().host("a:b").build().is_err());
+        assert!(Url::builder().host("a").path("%zz").build().is_err());
+
+        // a segment can't climb out of the path built so far
+        for segment in [".", ".."] {
+            let err = Url::builder()
+                .host("h")
+                .path_segment("files")
+                .path_segment(segment)
+                .path_segment("secret")
+                .build()
+                .unwrap_err();
+            assert!(err.kind() == ErrorKind::InvalidUrl);
+        }
+        let u = Url::builder()
+            .host("h")
+            .path_segment("...")
+            .path_segment(".x")
+            .build()
+            .unwrap();
+        assert!(u.path() == "/.../.x");
+    }
+
+    #[test]
+    fn test_url_setters() {
+        let mut u = Url::parse("http://example.com/a?b#c").unwrap();
+        assert!(u.set_host("exa/mple.com").is_err());
+        assert!(u.set_host("").is_err());
+        assert!(u.set_scheme("ht tp").is_err());
+        assert!(u.to_string() == "http://example.com/a?b#c");
+
+        u.set_scheme("https").unwrap();
+        u.set_host("[2001:db8::1]").unwrap();
+        u.set_port(Some(8443));
+        u.set_path("x y/./z").unwrap();
+        u.set_query("q=1 2").unwrap();
+        u.set_fragment("").unwrap();
+        assert!(u.to_string() == "https://[2001:db8::1]:8443/x%20y/z?q=1%202");
+        u.set_port(None);
+        assert!(u.effective_port() == Some(443));
+    }
+
+    #[test]
+    fn test_idna() {
+        let u = Url::parse("https://Bücher.example/straße?q=ü").unwrap();
+        assert!(u.hostname == "xn--bcher-kva.example");
+        assert!(u.unicode_hostname() == "bücher.example");
+        assert!(u.host_header() == "xn--bcher-kva.example");
+        assert!(u.to_string() == "https://xn--bcher-kva.example/stra%C3%9Fe?q=%C3%BC");
+
+        // percent encoded utf-8 is a unicode hostname too
+        assert!(
+            Url::parse("http://b%C3%BCcher.example/").unwrap().hostname == "xn--bcher-kva.example"
+        );
+        assert!(Url::new("http://例え.テスト:8080/").host() == "xn--r8jz45g.xn--zckzah:8080");
+
+        let mut u = Url::parse("http://example.com/").unwrap();
+        u.set_host("münchen.de").unwrap();
+        assert!(u.hostname == "xn--mnchen-3ya.de");
+        assert!(Url::builder().host("bücher..example").build().is_err());
+        assert!(Url::parse("http://b%C3%BCcher%2F.example/").is_err());
+
+        // a unix socket path isn't a domain name
+        let u = Url::parse("http+unix://%2Ftmp%2Fs%C3%BC.sock/").unwrap();
+        assert!(u.unix_socket() == Some(PathBuf::from("/tmp/sü.sock")));
+    }
+
+    #[test]
+    fn test_unix_socket() {
+        let u = Url::new("http+unix://%2Fvar%2Frun%2Fdocker.sock/v1.43/containers/json?all=1");
+        assert!(u.unix_socket() == Some(PathBuf::from("/var/run/docker.sock")));
+        assert!(u.resource() == "/v1.43/containers/json?all=1");
+        assert!(Url::new("http://localhost/").unix_socket().is_none());
+    }
+
+    #[test]
+    fn test_url_join() {
+        let base = Url::new("http://a.com:8080/b/c/d?q=1#f");
+        let cases = [
+            ("https://z.com/x", "https://z.com/x"),
+            ("//z.com/x", "http://z.com/x"),
+            ("/x/y?r=2", "http://a.com:8080/x/y?r=2"),
+            ("x", "http://a.com:8080/b/c/x"),
+            ("../x", "http://a.com:8080/b/x"),
+            ("./", "http://a.com:8080/b/c/"),
+            ("..", "http://a.com:8080/b/"),
+            ("?r=2", "http://a.com:8080/b/c/d?r=2"),
+            ("#g", "http://a.com:8080/b/c/d?q=1#g"),
+            (
+                "x?next=http://z.com",
+                "http://a.com:8080/b/c/x?next=http://z.com",
+            ),
+        ];
+
+        for (reference, expected) in cases {
+            assert_eq!(base.join(reference).unwrap().to_string(), expected);
+        }
+    }
+
+    // The examples of RFC 3986 section 5.4
+    #[test]
+    fn test_url_join_rfc3986() {
+        let base = Url::parse("http://a/b/c/d;p?q").unwrap();
+        let cases = [
+            ("g:h", "g:h"),
+            ("g", "http://a/b/c/g"),
+            ("./g", "http://a/b/c/g"),
+            ("g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("g?y", "http://a/b/c/g?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("g#s", "http://a/b/c/g#s"),
+            ("g?y#s", "http://a/b/c/g?y#s"),
+            (";x", "http://a/b/c/;x"),
+            ("g;x", "http://a/b/c/g;x"),
+            ("g;x?y#s", "http://a/b/c/g;x?y#s"),
+            ("", "http://a/b/c/d;p?q"),
+            (".", "http://a/b/c/"),
+            ("./", "http://a/b/c/"),
+            ("..", "http://a/b/"),
+            ("../", "http://a/b/"),
+            ("../g", "http://a/b/g"),
+            ("../..", "http://a/"),
+            ("../../", "http://a/"),
+            ("../../g", "http://a/g"),
+            // abnormal examples
+            ("../../../g", "http://a/g"),
+            ("../../../../g", "http://a/g"),
+            ("/./g", "http://a/g"),
+            ("/../g", "http://a/g"),
+            ("g.", "http://a/b/c/g."),
+            (".g", "http://a/b/c/.g"),
+            ("g..", "http://a/b/c/g.."),
+            ("..g", "http://a/b/c/..g"),
+            ("./../g", "http://a/b/g"),
+            ("./g/.", "http://a/b/c/g/"),
+            ("g/./h", "http://a/b/c/g/h"),
+            ("g/../h", "http://a/b/c/h"),
+            ("g;x=1/./y", "http://a/b/c/g;x=1/y"),
+            ("g;x=1/../y", "http://a/b/c/y"),
+            ("g?y/./x", "http://a/b/c/g?y/./x"),
+            ("g?y/../x", "http://a/b/c/g?y/../x"),
+            ("g#s/./x", "http://a/b/c/g#s/./x"),
+            ("g#s/../x", "http://a/b/c/g#s/../x"),
+            ("http:g", "http:g"),
+        ];
+
+        for (reference, expected) in cases {
+            match Url::parse(expected) {
+                Ok(_) => assert_eq!(base.join(reference).unwrap().to_string(), expected),
+                // "http:g" has no host, which this client refuses
+                Err(_) => assert!(base.join(reference).is_err()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_remove_dot_segments() {
+        assert!(remove_dot_segments("/a/b/c/./../../g") == "/a/g");
+        assert!(remove_dot_segments("mid/content=5/../6") == "mid/6");
+        assert!(remove_dot_segments("/..") == "/");
+        assert!(remove_dot_segments("").is_empty());
+    }
 }